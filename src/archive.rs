@@ -0,0 +1,74 @@
+/*
+compressed roms. files are recognised by their first bytes rather than
+their extension:
+
+    zip   "PK\x03\x04". holds any number of files, the rom is the entry
+          asked for by name, or else the first one ending in .gb or .gbc
+    gzip  0x1f 0x8b. a single compressed file, the rom
+
+anything else is taken to be a raw rom image and returned as it is
+*/
+use std::io::{Cursor, Read, Result};
+
+use crate::savestate::invalid;
+
+// the biggest cartridges are 8MB, anything larger is not a rom
+pub const MAX_ROM_SIZE: usize = 8 * 1024 * 1024;
+
+const ROM_EXTENSIONS: [&str; 2] = [".gb", ".gbc"];
+
+pub fn is_zip(data: &[u8]) -> bool {
+    return data.starts_with(b"PK\x03\x04");
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    return data.starts_with(&[0x1f, 0x8b]);
+}
+
+// the rom in `data`, unpacking it from a zip or gzip archive when it is one.
+// `entry` names the file to take from a zip
+pub fn unpack(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>> {
+    if is_zip(&data) { return unzip(&data, entry); }
+    if is_gzip(&data) { return read_limited(flate2::read::GzDecoder::new(&data[..])); }
+    return Ok(data);
+}
+
+fn read_limited<R: Read>(reader: R) -> Result<Vec<u8>> {
+    let mut rom = Vec::new();
+    reader.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom)?;
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(invalid("archive holds a file too big to be a rom"));
+    }
+    if rom.is_empty() {
+        return Err(invalid("archive contains no rom"));
+    }
+    return Ok(rom);
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    return ROM_EXTENSIONS.iter().any(|ext| name.ends_with(ext));
+}
+
+// whether zip entry `name` is the one asked for, by its full path or just its file name
+fn matches(name: &str, wanted: &str) -> bool {
+    return name == wanted || name.rsplit('/').next() == Some(wanted);
+}
+
+fn unzip(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data)).map_err(|e| invalid(&format!("bad zip archive: {}", e)))?;
+    let mut files = Vec::new();
+    for index in 0..zip.len() {
+        let file = zip.by_index(index).map_err(|e| invalid(&format!("bad zip archive: {}", e)))?;
+        if file.is_file() { files.push((index, file.name().to_string())); }
+    }
+
+    let found = match entry {
+        Some(wanted) => files.iter().find(|(_, name)| matches(name, wanted))
+            .ok_or_else(|| invalid(&format!("archive has no file named {}", wanted)))?,
+        None => files.iter().find(|(_, name)| is_rom_name(name))
+            .ok_or_else(|| invalid("archive holds no .gb or .gbc rom"))?
+    };
+    let file = zip.by_index(found.0).map_err(|e| invalid(&format!("bad zip archive: {}", e)))?;
+    return read_limited(file);
+}
@@ -0,0 +1,179 @@
+/*
+code/data log, which rom bytes a session used and how, for telling code
+from data when reverse engineering a game. the file is one byte of flags
+per rom byte, bank after bank (bank n starts at n * 0x4000), so it lines
+up with the rom and can be merged across sessions:
+
+    0x01  executed as an opcode
+    0x02  executed as an operand
+    0x04  read as data
+    0x08  graphics: copied into vram tile data
+
+the log is kept with MMU hooks. an opcode fetch notes where the
+instruction's operands are, so reads of them are not taken for data.
+graphics are what vram dma copies out of rom, and data the cpu reads and
+then writes unchanged to tile data (0x8000-0x97ff) as its very next
+write, the usual copy loop. any other write in between breaks the
+pair. nothing is logged while a boot rom is mapped, neither
+its own code nor its reads of the cartridge header are the game's
+*/
+use std::cell::{Ref, RefCell};
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::disasm;
+use crate::hooks::{Event, HookId};
+use crate::mmu::{Access, MMU};
+use crate::savestate::invalid;
+
+pub const CODE: u8 = 0x01;
+pub const OPERAND: u8 = 0x02;
+pub const DATA: u8 = 0x04;
+pub const GRAPHICS: u8 = 0x08;
+
+pub const BANK_SIZE: usize = 0x4000;
+
+pub struct Cdl {
+    flags: Vec<u8>
+}
+
+// the rom offset the cpu reaches at `address` with `bank` mapped there
+pub fn rom_offset(address: u16, bank: u16) -> Option<usize> {
+    return match address {
+        // only mbc1 in mode 1 puts anything but bank 0 here
+        0x0000..=0x3fff => Some(bank as usize * BANK_SIZE + address as usize),
+        0x4000..=0x7fff => Some(bank as usize * BANK_SIZE + (address as usize - 0x4000)),
+        _ => None
+    };
+}
+
+impl Cdl {
+    // an empty log for a rom of `rom_size` bytes
+    pub fn new(rom_size: usize) -> Cdl {
+        return Cdl { flags: vec![0; rom_size] };
+    }
+
+    pub fn from_bytes(data: &[u8], rom_size: usize) -> Result<Cdl> {
+        if data.len() != rom_size {
+            return Err(invalid(&format!("code/data log is for a rom of {} bytes, not {}", data.len(), rom_size)));
+        }
+        return Ok(Cdl { flags: data.to_vec() });
+    }
+
+    pub fn to_bytes(&self) -> &[u8] {
+        return &self.flags;
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        return fs::write(path, &self.flags);
+    }
+
+    // the log at `path` to add to, or a new one when there is none
+    pub fn load_or_new(path: &Path, rom_size: usize) -> Result<Cdl> {
+        if !path.exists() { return Ok(Cdl::new(rom_size)); }
+        return Cdl::from_bytes(&fs::read(path)?, rom_size);
+    }
+
+    pub fn mark(&mut self, offset: usize, flag: u8) {
+        if let Some(flags) = self.flags.get_mut(offset) { *flags |= flag; }
+    }
+
+    pub fn flags(&self, offset: usize) -> u8 {
+        return self.flags.get(offset).copied().unwrap_or(0);
+    }
+
+    pub fn banks(&self) -> usize {
+        return self.flags.len().div_ceil(BANK_SIZE);
+    }
+
+    // the flags of one bank's bytes
+    pub fn bank(&self, bank: usize) -> &[u8] {
+        let start = (bank * BANK_SIZE).min(self.flags.len());
+        let end = (start + BANK_SIZE).min(self.flags.len());
+        return &self.flags[start..end];
+    }
+
+    // how many bytes of `bank` have `flag` set
+    pub fn count(&self, bank: usize, flag: u8) -> usize {
+        return self.bank(bank).iter().filter(|f| *f & flag != 0).count();
+    }
+}
+
+struct State {
+    cdl: Cdl,
+    // the operand addresses of the instruction being run
+    operands: (u16, u16),
+    // rom offset and value of the last data read, for spotting copies to vram
+    last_data: Option<(usize, u8)>
+}
+
+impl State {
+    fn event(&mut self, event: &Event) {
+        if event.booting { return; }
+        let offset = rom_offset(event.address, event.bank);
+        match event.access {
+            Access::Execute => {
+                let len = disasm::instruction_length(event.value) as u16;
+                self.operands = (event.address.wrapping_add(1), event.address.wrapping_add(len));
+                if let Some(offset) = offset { self.cdl.mark(offset, CODE); }
+            },
+            Access::Read => if let Some(offset) = offset {
+                let (start, end) = self.operands;
+                if event.address >= start && event.address < end {
+                    self.cdl.mark(offset, OPERAND);
+                } else {
+                    self.cdl.mark(offset, DATA);
+                    self.last_data = Some((offset, event.value));
+                }
+            },
+            Access::Write => {
+                let tile_data = (0x8000..=0x97ff).contains(&event.address);
+                if let Some((offset, value)) = self.last_data.take() {
+                    if tile_data && value == event.value { self.cdl.mark(offset, GRAPHICS); }
+                }
+            },
+            Access::Dma => if let Some(offset) = offset { self.cdl.mark(offset, GRAPHICS); }
+        }
+    }
+}
+
+// keeps a Cdl up to date through hooks on an MMU
+pub struct Logger {
+    state: Rc<RefCell<State>>,
+    hooks: Vec<HookId>
+}
+
+impl Logger {
+    pub fn attach(memory_unit: &mut MMU, cdl: Cdl) -> Logger {
+        let state = Rc::new(RefCell::new(State { cdl, operands: (0, 0), last_data: None }));
+        let hooks = [
+            (Access::Execute, 0x0000, 0xffff),
+            (Access::Read, 0x0000, 0x7fff),
+            (Access::Write, 0x0000, 0xffff),
+            (Access::Dma, 0x0000, 0x7fff)
+        ];
+        let hooks = hooks.iter().map(|(access, start, end)| {
+            let state = state.clone();
+            memory_unit.add_hook(*access, *start, *end, Box::new(move |event| state.borrow_mut().event(event)))
+        }).collect();
+        return Logger { state, hooks };
+    }
+
+    pub fn cdl(&self) -> Ref<'_, Cdl> {
+        return Ref::map(self.state.borrow(), |state| &state.cdl);
+    }
+
+    // removes the hooks and hands back the log
+    pub fn detach(self, memory_unit: &mut MMU) -> Cdl {
+        for id in self.hooks.iter() {
+            memory_unit.remove_hook(*id);
+        }
+        let state = match Rc::try_unwrap(self.state) {
+            Ok(state) => state.into_inner(),
+            Err(_) => unreachable!("the hooks holding the log were removed")
+        };
+        return state.cdl;
+    }
+}
@@ -0,0 +1,187 @@
+/*
+game boy color hardware that the MMU maps in when running in cgb mode:
+
+    0x8000-0x9fff  two vram banks picked with VBK (0xff4f). bank 1 holds
+                   more tile data and, at 0x9800-0x9fff, the attributes of
+                   the tiles in the bank 0 maps
+    0xc000-0xcfff  wram bank 0
+    0xd000-0xdfff  wram banks 1-7 picked with SVBK (0xff70), 0 selects 1
+    0xff4d         KEY1, bit 0 arms a speed switch that the next STOP
+                   performs, bit 7 reads back the current speed
+    0xff68-0xff6b  BCPS/BCPD and OCPS/OCPD, an index (bit 7 auto
+                   increments it after writes) into 64 bytes of background
+                   and object palette ram, 8 palettes of 4 rgb555 colours
+    0xff51-0xff55  vram dma, see hdma.rs
+
+double speed runs the cpu, and the serial port clocked from it, twice
+as fast against everything else: a frame takes twice the cycles and vram
+dma stalls the cpu for twice as many. the tile attributes and the cgb
+priority rules between background and objects are left to the ppu,
+which is not emulated yet
+*/
+use std::io::Result;
+
+use crate::hdma::Hdma;
+use crate::savestate::{Savable, StateReader, StateWriter};
+
+pub const VRAM_BANK_SIZE: usize = 0x2000;
+pub const WRAM_BANK_SIZE: usize = 0x1000;
+
+pub struct Cgb {
+    pub vram: Vec<u8>,
+    pub wram: Vec<u8>,
+    pub bg_palettes: [u8; 64],
+    pub obj_palettes: [u8; 64],
+    pub hdma: Hdma,
+    vram_bank: u8,
+    wram_bank: u8,
+    bcps: u8,
+    ocps: u8,
+    double_speed: bool,
+    speed_switch_armed: bool
+}
+
+fn palette_color(ram: &[u8; 64], palette: u8, color: u8) -> u16 {
+    let i = ((palette & 7) as usize * 4 + (color & 3) as usize) * 2;
+    return (ram[i] as u16) | ((ram[i + 1] as u16) << 8);
+}
+
+impl Cgb {
+    pub fn new() -> Cgb {
+        return Cgb {
+            vram: vec![0; VRAM_BANK_SIZE * 2],
+            wram: vec![0; WRAM_BANK_SIZE * 8],
+            bg_palettes: [0xff; 64],
+            obj_palettes: [0; 64],
+            hdma: Hdma::new(),
+            vram_bank: 0,
+            wram_bank: 1,
+            bcps: 0,
+            ocps: 0,
+            double_speed: false,
+            speed_switch_armed: false
+        };
+    }
+
+    pub fn vram_bank(&self) -> u8 {
+        return self.vram_bank;
+    }
+
+    pub fn wram_bank(&self) -> u8 {
+        return self.wram_bank;
+    }
+
+    pub fn double_speed(&self) -> bool {
+        return self.double_speed;
+    }
+
+    // performs an armed speed switch, called by STOP. returns true if the speed changed
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed { return false; }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        return true;
+    }
+
+    // rgb555 colour `color` of background palette `palette`
+    pub fn bg_color(&self, palette: u8, color: u8) -> u16 {
+        return palette_color(&self.bg_palettes, palette, color);
+    }
+
+    pub fn obj_color(&self, palette: u8, color: u8) -> u16 {
+        return palette_color(&self.obj_palettes, palette, color);
+    }
+
+    // true for the addresses this module owns
+    pub fn maps(address: u16) -> bool {
+        return matches!(address, 0x8000..=0x9fff | 0xc000..=0xdfff | 0xff4d | 0xff4f | 0xff51..=0xff55 | 0xff68..=0xff6b | 0xff70);
+    }
+
+    fn wram_index(&self, address: u16) -> usize {
+        let offset = address as usize & 0x0fff;
+        return match address {
+            0xc000..=0xcfff => offset,
+            _ => self.wram_bank as usize * WRAM_BANK_SIZE + offset
+        };
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        return match address {
+            0x8000..=0x9fff => self.vram[self.vram_bank as usize * VRAM_BANK_SIZE + (address as usize & 0x1fff)],
+            0xc000..=0xdfff => self.wram[self.wram_index(address)],
+            0xff4d => ((self.double_speed as u8) << 7) | 0x7e | self.speed_switch_armed as u8,
+            0xff4f => 0xfe | self.vram_bank,
+            0xff51..=0xff55 => self.hdma.read(address),
+            0xff68 => self.bcps | 0x40,
+            0xff69 => self.bg_palettes[(self.bcps & 0x3f) as usize],
+            0xff6a => self.ocps | 0x40,
+            0xff6b => self.obj_palettes[(self.ocps & 0x3f) as usize],
+            0xff70 => 0xf8 | self.wram_bank,
+            _ => 0xff
+        };
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9fff => self.vram[self.vram_bank as usize * VRAM_BANK_SIZE + (address as usize & 0x1fff)] = value,
+            0xc000..=0xdfff => {
+                let i = self.wram_index(address);
+                self.wram[i] = value;
+            },
+            0xff4d => self.speed_switch_armed = value & 1 != 0,
+            0xff4f => self.vram_bank = value & 1,
+            0xff51..=0xff55 => self.hdma.write(address, value),
+            0xff68 => self.bcps = value & 0xbf,
+            0xff69 => {
+                self.bg_palettes[(self.bcps & 0x3f) as usize] = value;
+                if self.bcps & 0x80 != 0 { self.bcps = 0x80 | ((self.bcps + 1) & 0x3f); }
+            },
+            0xff6a => self.ocps = value & 0xbf,
+            0xff6b => {
+                self.obj_palettes[(self.ocps & 0x3f) as usize] = value;
+                if self.ocps & 0x80 != 0 { self.ocps = 0x80 | ((self.ocps + 1) & 0x3f); }
+            },
+            0xff70 => self.wram_bank = (value & 7).max(1),
+            _ => {}
+        }
+    }
+}
+
+impl Default for Cgb {
+    fn default() -> Cgb {
+        return Cgb::new();
+    }
+}
+
+impl Savable for Cgb {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.wram);
+        writer.write_bytes(&self.bg_palettes);
+        writer.write_bytes(&self.obj_palettes);
+        writer.write_u8(self.vram_bank);
+        writer.write_u8(self.wram_bank);
+        writer.write_u8(self.bcps);
+        writer.write_u8(self.ocps);
+        writer.write_bool(self.double_speed);
+        writer.write_bool(self.speed_switch_armed);
+        self.hdma.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let mut loaded = Cgb::new();
+        reader.read_into(&mut loaded.vram)?;
+        reader.read_into(&mut loaded.wram)?;
+        reader.read_into(&mut loaded.bg_palettes)?;
+        reader.read_into(&mut loaded.obj_palettes)?;
+        loaded.vram_bank = reader.read_u8()? & 1;
+        loaded.wram_bank = (reader.read_u8()? & 7).max(1);
+        loaded.bcps = reader.read_u8()?;
+        loaded.ocps = reader.read_u8()?;
+        loaded.double_speed = reader.read_bool()?;
+        loaded.speed_switch_armed = reader.read_bool()?;
+        loaded.hdma.load_state(reader)?;
+        *self = loaded;
+        return Ok(());
+    }
+}
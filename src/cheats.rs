@@ -0,0 +1,156 @@
+/*
+cheat codes:
+
+    gameshark   8 hex digits TTVVLLHH. writes VV to ram address HHLL at
+                the start of every frame. TT is 01 for plain writes, the
+                80-8f codes that pick a cartridge ram bank write to
+                whatever bank the mbc has mapped. only ram addresses are
+                taken, vram, cartridge ram, wram (0x8000-0xdfff) and
+                hram (0xff80-0xfffe), a write to the rom or a register
+                would switch banks or hardware every frame
+
+    game genie  ABC-DEF or ABC-DEF-GHI. replaces reads of rom address
+                (F ^ 0xf)CDE with AB. the optional GHI is a compare byte,
+                GI rotated right by 2 and xor'd with 0xba, and the read
+                is only replaced when the rom holds that byte there,
+                which keeps the code to the right bank. H is not used
+
+game genie codes are looked up on every rom read so MMU keeps a flag to
+skip them entirely while none are enabled
+*/
+use std::io::Result;
+
+use crate::savestate::invalid;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Code {
+    GameShark { bank: u8, address: u16, value: u8 },
+    GameGenie { address: u16, value: u8, compare: Option<u8> }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cheat {
+    pub text: String,
+    pub code: Code,
+    pub enabled: bool
+}
+
+fn hex_digits(text: &str) -> Option<Vec<u8>> {
+    return text.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect();
+}
+
+impl Code {
+    pub fn parse(text: &str) -> Option<Code> {
+        let text = text.trim();
+        if text.contains('-') {
+            let digits = hex_digits(&text.replace('-', ""))?;
+            let groups: Vec<&str> = text.split('-').collect();
+            if groups.iter().any(|g| g.len() != 3) || (digits.len() != 6 && digits.len() != 9) { return None; }
+
+            let d = |i: usize| digits[i] as u16;
+            let value = ((d(0) << 4) | d(1)) as u8;
+            let address = ((d(5) ^ 0xf) << 12) | (d(2) << 8) | (d(3) << 4) | d(4);
+            if address >= 0x8000 { return None; }
+            let compare = if digits.len() == 9 {
+                Some((((d(6) << 4) | d(8)) as u8).rotate_right(2) ^ 0xba)
+            } else {
+                None
+            };
+            return Some(Code::GameGenie { address, value, compare });
+        }
+
+        let digits = hex_digits(text)?;
+        if digits.len() != 8 { return None; }
+        let byte = |i: usize| (digits[i * 2] << 4) | digits[i * 2 + 1];
+        let address = ((byte(3) as u16) << 8) | byte(2) as u16;
+        if !matches!(address, 0x8000..=0xdfff | 0xff80..=0xfffe) { return None; }
+        return Some(Code::GameShark { bank: byte(0), value: byte(1), address });
+    }
+}
+
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    rom_patches: bool
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        return Cheats { cheats: Vec::new(), rom_patches: false };
+    }
+
+    fn update(&mut self) {
+        self.rom_patches = self.cheats.iter().any(|c| c.enabled && matches!(c.code, Code::GameGenie { .. }));
+    }
+
+    // adds an enabled code, returns its index
+    pub fn add(&mut self, text: &str) -> Result<usize> {
+        let code = match Code::parse(text) {
+            Some(code) => code,
+            None => return Err(invalid(&format!("{} is not a gameshark or game genie code", text.trim())))
+        };
+        self.cheats.push(Cheat { text: text.trim().to_uppercase(), code, enabled: true });
+        self.update();
+        return Ok(self.cheats.len() - 1);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index >= self.cheats.len() { return None; }
+        let cheat = self.cheats.remove(index);
+        self.update();
+        return Some(cheat);
+    }
+
+    // returns false when there is no such code
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => cheat.enabled = enabled,
+            None => return false
+        }
+        self.update();
+        return true;
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        return &self.cheats;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.cheats.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.cheats.is_empty();
+    }
+
+    // whether any game genie code is enabled
+    pub fn patches_rom(&self) -> bool {
+        return self.rom_patches;
+    }
+
+    // what a read of rom `address` returns when the rom holds `original` there
+    pub fn read_rom(&self, address: u16, original: u8) -> u8 {
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            if let Code::GameGenie { address: a, value, compare } = cheat.code {
+                if a == address && compare.map(|c| c == original).unwrap_or(true) { return value; }
+            }
+        }
+        return original;
+    }
+
+    // the (address, value) writes the enabled gameshark codes make each frame
+    pub fn writes(&self) -> Vec<(u16, u8)> {
+        return self.cheats.iter()
+            .filter(|c| c.enabled)
+            .filter_map(|c| match c.code {
+                Code::GameShark { address, value, .. } => Some((address, value)),
+                _ => None
+            })
+            .collect();
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Cheats {
+        return Cheats::new();
+    }
+}
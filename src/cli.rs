@@ -0,0 +1,318 @@
+/*
+command line of the gb_emulator binary, see USAGE. parsing lives here
+rather than in main.rs so the options can be checked without a window
+*/
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::cpu::Z80;
+use crate::mmu::MMU;
+use crate::model::Model;
+use crate::palettes::Palette;
+use crate::rom_loader;
+use crate::serial::{Capture, LinkCable, Loopback, SocketLink};
+use crate::symbols::{self, Symbols};
+use crate::trace::Tracer;
+
+pub const USAGE: &str = "\
+usage: gb_emulator <command> [options]
+
+commands:
+    run <rom>                     play a rom
+    info <rom>                    print the cartridge header
+    disasm <rom> [start] [end]    disassemble, start and end are hex rom offsets, with
+                                  labels from the .sym beside the rom
+    debug <rom>                   step through a rom in the debugger
+    test <rom>                    run a test rom, the exit status is 0 when it passes
+
+roms can be raw images or zip or gzip archives of one
+
+options for run, debug and test:
+    --model <name>        dmg0, dmg, mgb, sgb, cgb or agb (default: picked from the header)
+    --entry <name>        the rom to take from a zip (default: the first .gb or .gbc in it)
+    --patch <file>        apply an ips, ups or bps patch (default: one named like the rom beside it)
+
+options for run and debug:
+    --boot-rom <file>     run this boot rom before the cartridge
+    --link <cable>        serial cable: loopback, stdout, listen:<port> or connect:<port>
+    --trace <file>        write an instruction trace
+    --trace-range <a-b>   only trace instructions at pcs a to b, in hex
+    --trace-bank <n>      only trace instructions run from this bank, in hex
+    --sym <file>          labels for the debugger and profiler (default: the .sym beside the rom),
+                          and for the trace when given
+    --cheat <code>        enable a gameshark or game genie code, can be repeated
+    --cheats <file>       enable the codes in a file, one per line, # starts a comment
+    --cdl <file>          log which rom bytes are code, data or graphics, adding to the file
+    --profile <file>      count cycles per function and bank, writing folded stacks for flame graphs
+
+options for debug:
+    --gdb <port>          wait for gdb on localhost:<port> instead of the debugger prompt
+
+options for run:
+    --scale <n>           window scale (default 3)
+    --palette <name>      green, pocket or four hex colours like e0f8d0,88c070,346856,081820
+    --save-dir <dir>      where save states go (default: beside the rom)
+    --headless <frames>   run this many frames without a window and exit
+    --speed <multiplier>  emulation speed from 0.25 to 8 (default 1)
+    --record <file>       record the joypad from power on to a movie
+    --play <file>         play a movie back, then hand the joypad back to the keyboard
+
+options for test:
+    --timeout <seconds>   emulated seconds before giving up (default 120)
+";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Link {
+    Loopback,
+    Stdout,
+    Listen(u16),
+    Connect(u16)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub model: Option<Model>,
+    pub entry: Option<String>,
+    pub patch: Option<PathBuf>,
+    pub boot_rom: Option<PathBuf>,
+    pub link: Option<Link>,
+    pub trace: Option<PathBuf>,
+    pub trace_range: Option<(u16, u16)>,
+    pub trace_bank: Option<u16>,
+    pub sym: Option<PathBuf>,
+    pub gdb: Option<u16>,
+    pub cheats: Vec<String>,
+    pub cheat_file: Option<PathBuf>,
+    pub cdl: Option<PathBuf>,
+    pub profile: Option<PathBuf>,
+    pub scale: u32,
+    pub palette: Palette,
+    pub save_dir: Option<PathBuf>,
+    pub headless: Option<u32>,
+    pub speed: f32,
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    pub timeout: u64
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    Run(Options),
+    Info(PathBuf),
+    Disasm { rom: PathBuf, start: Option<usize>, end: Option<usize> },
+    Debug(Options),
+    Test(Options),
+    Help
+}
+
+impl Options {
+    pub fn new(rom: PathBuf) -> Options {
+        return Options {
+            rom,
+            model: None,
+            entry: None,
+            patch: None,
+            boot_rom: None,
+            link: None,
+            trace: None,
+            trace_range: None,
+            trace_bank: None,
+            sym: None,
+            gdb: None,
+            cheats: Vec::new(),
+            cheat_file: None,
+            cdl: None,
+            profile: None,
+            scale: 3,
+            palette: Palette::default(),
+            save_dir: None,
+            headless: None,
+            speed: 1.0,
+            record: None,
+            play: None,
+            timeout: 120
+        };
+    }
+
+    // the rom, out of its archive and with its patch applied
+    pub fn read_rom(&self) -> io::Result<Vec<u8>> {
+        return rom_loader::read_rom_with(&self.rom, self.entry.as_deref(), self.patch.as_deref());
+    }
+
+    // the labels of --sym or the symbol file beside the rom, if there is one
+    pub fn symbols(&self) -> io::Result<Option<Symbols>> {
+        return match self.sym.clone().or_else(|| symbols::find_beside(&self.rom)) {
+            Some(path) => Ok(Some(Symbols::load(&path)?)),
+            None => Ok(None)
+        };
+    }
+
+    // builds the machine the options describe, ready to run the rom
+    pub fn machine(&self) -> io::Result<Z80> {
+        let mut memory_unit = MMU::new();
+        rom_loader::insert_rom(&self.read_rom()?, &mut memory_unit, self.model);
+        if let Some(link) = self.link {
+            memory_unit.serial.connect(Some(link.cable()?));
+        }
+
+        let mut codes = self.cheats.clone();
+        if let Some(path) = &self.cheat_file {
+            let text = fs::read_to_string(path)?;
+            let lines = text.lines().map(|l| l.split('#').next().unwrap().trim()).filter(|l| !l.is_empty());
+            codes.extend(lines.map(|l| l.split_whitespace().next().unwrap().to_string()));
+        }
+        for code in codes.iter() {
+            memory_unit.cheats.add(code)?;
+        }
+
+        let boot_rom = match &self.boot_rom {
+            Some(path) => Some(fs::read(path)?),
+            None => None
+        };
+        let booting = boot_rom.is_some();
+        if let Some(boot) = boot_rom {
+            memory_unit.set_boot_rom(boot);
+        }
+
+        let mut cpu = Z80::new(memory_unit);
+        // a boot rom starts from 0 with everything cleared and sets the registers itself
+        if booting {
+            cpu.set_register("ime", 0);
+        } else {
+            cpu.reset();
+        }
+        if let Some(path) = &self.trace {
+            let mut tracer = Tracer::to_file(path)?;
+            // label lines would break diffs against other emulators' traces, so only when asked for
+            if self.sym.is_some() { tracer.set_symbols(self.symbols()?); }
+            tracer.set_range(self.trace_range);
+            tracer.set_bank(self.trace_bank);
+            cpu.set_tracer(Some(tracer))?;
+        }
+        return Ok(cpu);
+    }
+}
+
+impl Link {
+    pub fn parse(text: &str) -> Option<Link> {
+        let port = |p: &str| p.parse::<u16>().ok();
+        return match text.split_once(':') {
+            None if text == "loopback" => Some(Link::Loopback),
+            None if text == "stdout" => Some(Link::Stdout),
+            Some(("listen", p)) => port(p).map(Link::Listen),
+            Some(("connect", p)) => port(p).map(Link::Connect),
+            _ => None
+        };
+    }
+
+    // plugs in the cable, listen waits until the other emulator connects
+    pub fn cable(&self) -> io::Result<Box<dyn LinkCable>> {
+        return Ok(match self {
+            Link::Loopback => Box::new(Loopback),
+            Link::Stdout => Box::new(Capture::stdout()),
+            Link::Listen(port) => Box::new(SocketLink::listen(*port)?),
+            Link::Connect(port) => Box::new(SocketLink::connect(*port)?)
+        });
+    }
+}
+
+fn hex_offset(text: &str) -> Result<usize, String> {
+    return usize::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("bad offset {}", text));
+}
+
+fn hex(option: &str, value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches('$').trim_start_matches("0x");
+    return u16::from_str_radix(digits, 16).map_err(|_| format!("bad value {} for {}", value, option));
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    return value.parse().map_err(|_| format!("bad value {} for {}", value, option));
+}
+
+// the options after the rom of run, debug and test
+fn parse_options(rom: &str, args: &[String]) -> Result<Options, String> {
+    let mut options = Options::new(PathBuf::from(rom));
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        let value = match args.next() {
+            Some(value) => value.as_str(),
+            None => return Err(format!("{} needs a value", option))
+        };
+        match option.as_str() {
+            "--model" => options.model = Some(Model::parse(value).ok_or(format!("unknown model {}", value))?),
+            "--entry" => options.entry = Some(value.to_string()),
+            "--patch" => options.patch = Some(PathBuf::from(value)),
+            "--boot-rom" => options.boot_rom = Some(PathBuf::from(value)),
+            "--link" => options.link = Some(Link::parse(value).ok_or(format!("unknown link cable {}", value))?),
+            "--trace" => options.trace = Some(PathBuf::from(value)),
+            "--trace-range" => {
+                let (start, end) = value.split_once('-').ok_or(format!("bad value {} for {}", value, option))?;
+                options.trace_range = Some((hex(option, start)?, hex(option, end)?));
+            },
+            "--trace-bank" => options.trace_bank = Some(hex(option, value)?),
+            "--sym" => options.sym = Some(PathBuf::from(value)),
+            "--gdb" => options.gdb = Some(number(option, value)?),
+            "--cheat" => options.cheats.push(value.to_string()),
+            "--cheats" => options.cheat_file = Some(PathBuf::from(value)),
+            "--cdl" => options.cdl = Some(PathBuf::from(value)),
+            "--profile" => options.profile = Some(PathBuf::from(value)),
+            "--scale" => options.scale = number::<u32>(option, value)?.clamp(1, 16),
+            "--palette" => options.palette = Palette::parse(value).ok_or(format!("unknown palette {}", value))?,
+            "--save-dir" => options.save_dir = Some(PathBuf::from(value)),
+            "--headless" => options.headless = Some(number(option, value)?),
+            "--speed" => {
+                options.speed = number(option, value)?;
+                if !(0.25..=8.0).contains(&options.speed) { return Err(format!("speed {} is not between 0.25 and 8", value)); }
+            },
+            "--record" => options.record = Some(PathBuf::from(value)),
+            "--play" => options.play = Some(PathBuf::from(value)),
+            "--timeout" => options.timeout = number(option, value)?,
+            _ => return Err(format!("unknown option {}", option))
+        }
+    }
+    // movies replay from power on with nothing but the joypad feeding the machine
+    if (options.record.is_some() || options.play.is_some()) && (options.boot_rom.is_some() || options.link.is_some()) {
+        return Err(String::from("movies can't be used with --boot-rom or --link"));
+    }
+    // the codes are not part of the movie, playback would run without them
+    if (options.record.is_some() || options.play.is_some()) && (!options.cheats.is_empty() || options.cheat_file.is_some()) {
+        return Err(String::from("movies can't be used with --cheat or --cheats"));
+    }
+    if (options.trace_range.is_some() || options.trace_bank.is_some()) && options.trace.is_none() {
+        return Err(String::from("--trace-range and --trace-bank need --trace"));
+    }
+    if options.record.is_some() && options.play.is_some() {
+        return Err(String::from("--record and --play can't be used together"));
+    }
+    return Ok(options);
+}
+
+// `args` without the program name
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Ok(Command::Help)
+    };
+    if command == "help" || command == "-h" || command == "--help" { return Ok(Command::Help); }
+
+    let rom = match rest.first() {
+        Some(rom) => rom.as_str(),
+        None => return Err(format!("{} needs a rom", command))
+    };
+    return match command {
+        "run" => Ok(Command::Run(parse_options(rom, &rest[1..])?)),
+        "debug" => Ok(Command::Debug(parse_options(rom, &rest[1..])?)),
+        "test" => Ok(Command::Test(parse_options(rom, &rest[1..])?)),
+        "info" if rest.len() == 1 => Ok(Command::Info(PathBuf::from(rom))),
+        "disasm" if rest.len() <= 3 => Ok(Command::Disasm {
+            rom: PathBuf::from(rom),
+            start: rest.get(1).map(|s| hex_offset(s)).transpose()?,
+            end: rest.get(2).map(|s| hex_offset(s)).transpose()?
+        }),
+        "info" | "disasm" => Err(format!("too many arguments for {}", command)),
+        _ => Err(format!("unknown command {}", command))
+    };
+}
+
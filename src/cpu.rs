@@ -8,6 +8,10 @@ use crate::{
     SWAPr_x, undefined, XORr_x,
 };
 use crate::{ mem_access_w, mem_access_b };
+use crate::savestate::{Savable, StateReader, StateWriter};
+use crate::profiler::{Profiler, Step};
+use crate::trace::Tracer;
+use std::io::{Error, Result};
 
 
 const ZERO_FLAG: u8 = 0x80;
@@ -15,29 +19,37 @@ const SUB_FLAG: u8 = 0x40;
 const HCARRY_FLAG: u8 = 0x20;
 const CARRY_FLAG: u8 = 0x10;
 
+pub const CYCLES_PER_SECOND: u64 = 4_194_304;
+// t cycles in the 154 lines of one frame at normal speed
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
 
 pub struct Z80 {
-    memory_unit: MMU,
-    global_m: u8,
-    global_t: u8,
-    last_m: u8,
-    last_t: u8,
-    a: u8,
-    b: u8,
-    c: u8,
-    d: u8,
-    e: u8,
-    f: u8,
-    h: u8,
-    l: u8,
-    pc: u16,
-    sp: u16,
-    halt: bool,
-    ime: bool
+    pub(crate) memory_unit: MMU,
+    pub(crate) global_m: u8,
+    pub(crate) global_t: u8,
+    pub(crate) last_m: u16,
+    pub(crate) last_t: u16,
+    pub(crate) a: u8,
+    pub(crate) b: u8,
+    pub(crate) c: u8,
+    pub(crate) d: u8,
+    pub(crate) e: u8,
+    pub(crate) f: u8,
+    pub(crate) h: u8,
+    pub(crate) l: u8,
+    pub(crate) pc: u16,
+    pub(crate) sp: u16,
+    pub(crate) halt: bool,
+    pub(crate) ime: bool,
+    pub(crate) tracer: Option<Tracer>,
+    // what stopped the tracer, kept for set_tracer to hand back
+    pub(crate) trace_error: Option<Error>,
+    pub(crate) profiler: Option<Profiler>
 }
 
 impl Z80 {
-    pub fn new(mut memory_unit: MMU) -> Z80 {
+    pub fn new(memory_unit: MMU) -> Z80 {
         return Z80 {
             memory_unit,
             global_m: 0,
@@ -55,29 +67,249 @@ impl Z80 {
             pc: 0,
             sp: 0,
             halt: false,
-            ime: true
+            ime: true,
+            tracer: None,
+            trace_error: None,
+            profiler: None
         }
     }
 
+    // register values the boot rom of the emulated model leaves behind when it jumps to the cartridge
     pub fn reset(&mut self) {
-        
+        let regs = self.memory_unit.model().registers(self.memory_unit.cgb.is_some());
+        self.a = (regs.af >> 8) as u8; self.f = regs.af as u8 & 0xf0;
+        self.b = (regs.bc >> 8) as u8; self.c = regs.bc as u8;
+        self.d = (regs.de >> 8) as u8; self.e = regs.de as u8;
+        self.h = (regs.hl >> 8) as u8; self.l = regs.hl as u8;
+        self.sp = 0xfffe;
+        self.pc = 0x0100;
+        self.halt = false;
+        self.ime = false;
+        self.last_m = 0; self.last_t = 0;
+    }
+
+    // runs instructions for one frame, returns the t cycles taken
+    pub fn run_frame(&mut self) -> u32 {
+        let frame = if self.memory_unit.double_speed() { CYCLES_PER_FRAME * 2 } else { CYCLES_PER_FRAME };
+        self.memory_unit.apply_cheats();
+        let mut cycles = 0;
+        while cycles < frame {
+            self.run();
+            cycles += self.last_t.max(4) as u32;
+        }
+        return cycles;
+    }
+
+    // t cycles taken by the last instruction
+    pub fn last_cycles(&self) -> u16 {
+        return self.last_t;
     }
 
     pub fn run(&mut self) -> u8 {
-        if let Some(op) = self.memory_unit.get_b(self.pc) {
-            self.pc = self.pc.wrapping_add(1);
-            isa_map[op as usize](self);
+        let profiling = self.profiler.is_some();
+        let (pc, sp) = (self.pc, self.sp);
+        let mut op = None;
+
+        let pending = self.memory_unit.pending_interrupts();
+        if pending != 0 {
+            self.halt = false;
+        }
+
+        let interrupt = self.ime && pending != 0;
+        if interrupt {
+            self.interrupt(pending);
+        } else if self.halt {
+            self.last_m = 1; self.last_t = 4;
+        } else {
+            if self.tracer.is_some() {
+                self.trace();
+            }
+
+            if let Some(opcode) = self.memory_unit.fetch(self.pc) {
+                op = Some(opcode);
+                self.pc = self.pc.wrapping_add(1);
+                isa_map[opcode as usize](self);
+            }
         }
 
+        //cgb vram dma started by this instruction or the last hblank stops the cpu
+        let stall = self.memory_unit.take_dma_stall();
+        self.last_m += stall / 4; self.last_t += stall;
+
+        self.memory_unit.tick(self.last_t as u32);
+        if profiling { self.profile(pc, sp, op, interrupt); }
         return self.a;
     }
 
+    fn profile(&mut self, pc: u16, sp: u16, op: Option<u8>, interrupt: bool) {
+        let step = Step {
+            pc,
+            bank: self.memory_unit.bank_at(pc),
+            op,
+            interrupt,
+            cycles: self.last_t.max(4),
+            sp_before: sp,
+            sp_after: self.sp,
+            next_pc: self.pc,
+            next_bank: self.memory_unit.bank_at(self.pc)
+        };
+        if let Some(profiler) = self.profiler.as_mut() { profiler.record(step); }
+    }
+
+    // jumps to the handler of the highest priority pending interrupt (lowest bit)
+    fn interrupt(&mut self, pending: u8) {
+        let bit = pending.trailing_zeros() as u16;
+        self.memory_unit.clear_interrupt(1 << bit);
+        self.ime = false;
+        self.sp = self.sp.wrapping_sub(2);
+        mem_access_w!(self.memory_unit, self.sp, self.pc);
+        self.pc = 0x40 + bit * 8;
+        self.last_m = 5; self.last_t = 20;
+    }
+
+    fn trace(&mut self) {
+        let mut tracer = self.tracer.take().unwrap();
+        match tracer.trace(self) {
+            Ok(()) => self.tracer = Some(tracer),
+            Err(e) => self.trace_error = Some(e)
+        }
+    }
+
+    // replaces the tracer, flushing the old one. a write that failed, flushing
+    // or earlier when it stopped the old tracer, comes back as the error
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Result<()> {
+        let old = self.tracer.take();
+        self.tracer = tracer;
+        if let Some(e) = self.trace_error.take() { return Err(e); }
+        return match old {
+            Some(mut old) => old.flush(),
+            None => Ok(())
+        };
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        return self.profiler.as_ref();
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        return self.profiler.take();
+    }
+
+    pub fn memory(&self) -> &MMU {
+        return &self.memory_unit;
+    }
+
+    pub fn memory_mut(&mut self) -> &mut MMU {
+        return &mut self.memory_unit;
+    }
+
+    // registers by name: 8 bit (a, f, b...), pairs (af, bc, de, hl), sp, pc, flags (zf, nf, hf, cf), ime and halt
+    pub fn register(&self, name: &str) -> Option<u16> {
+        let pair = |hi: u8, lo: u8| ((hi as u16) << 8) | lo as u16;
+        let flag = |mask: u8| (self.f & mask != 0) as u16;
+        return match name.to_ascii_lowercase().as_str() {
+            "a" => Some(self.a as u16),
+            "f" => Some(self.f as u16),
+            "b" => Some(self.b as u16),
+            "c" => Some(self.c as u16),
+            "d" => Some(self.d as u16),
+            "e" => Some(self.e as u16),
+            "h" => Some(self.h as u16),
+            "l" => Some(self.l as u16),
+            "af" => Some(pair(self.a, self.f)),
+            "bc" => Some(pair(self.b, self.c)),
+            "de" => Some(pair(self.d, self.e)),
+            "hl" => Some(pair(self.h, self.l)),
+            "sp" => Some(self.sp),
+            "pc" => Some(self.pc),
+            "zf" => Some(flag(ZERO_FLAG)),
+            "nf" => Some(flag(SUB_FLAG)),
+            "hf" => Some(flag(HCARRY_FLAG)),
+            "cf" => Some(flag(CARRY_FLAG)),
+            "ime" => Some(self.ime as u16),
+            "halt" => Some(self.halt as u16),
+            _ => None
+        };
+    }
+
+    // returns false for an unknown register name
+    pub fn set_register(&mut self, name: &str, value: u16) -> bool {
+        let hi = (value >> 8) as u8;
+        let lo = (value & 0xff) as u8;
+        let set_flag = |f: u8, mask: u8| if value != 0 { f | mask } else { f & !mask };
+        match name.to_ascii_lowercase().as_str() {
+            "a" => self.a = lo,
+            "f" => self.f = lo & 0xf0,
+            "b" => self.b = lo,
+            "c" => self.c = lo,
+            "d" => self.d = lo,
+            "e" => self.e = lo,
+            "h" => self.h = lo,
+            "l" => self.l = lo,
+            "af" => { self.a = hi; self.f = lo & 0xf0; },
+            "bc" => { self.b = hi; self.c = lo; },
+            "de" => { self.d = hi; self.e = lo; },
+            "hl" => { self.h = hi; self.l = lo; },
+            "sp" => self.sp = value,
+            "pc" => self.pc = value,
+            "zf" => self.f = set_flag(self.f, ZERO_FLAG),
+            "nf" => self.f = set_flag(self.f, SUB_FLAG),
+            "hf" => self.f = set_flag(self.f, HCARRY_FLAG),
+            "cf" => self.f = set_flag(self.f, CARRY_FLAG),
+            "ime" => self.ime = value != 0,
+            "halt" => self.halt = value != 0,
+            _ => return false
+        }
+        return true;
+    }
+
     pub fn test(&self) -> u16 {
         return mem_access_w!(self.memory_unit, 5);
     }
 }
 
-static undefined: fn(u16) = |x: u16| { panic!("Hit undefined instruction at {:?}", stringify!(x - 1)); };
+impl Savable for Z80 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for r in [self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l].iter() {
+            writer.write_u8(*r);
+        }
+        writer.write_u16(self.pc);
+        writer.write_u16(self.sp);
+        writer.write_bool(self.halt);
+        writer.write_bool(self.ime);
+        writer.write_u8(self.global_m);
+        writer.write_u8(self.global_t);
+        writer.write_u16(self.last_m);
+        writer.write_u16(self.last_t);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let mut regs = [0u8; 8];
+        for r in regs.iter_mut() {
+            *r = reader.read_u8()?;
+        }
+        let pc = reader.read_u16()?;
+        let sp = reader.read_u16()?;
+        let halt = reader.read_bool()?;
+        let ime = reader.read_bool()?;
+        let global = [reader.read_u8()?, reader.read_u8()?];
+        let last = [reader.read_u16()?, reader.read_u16()?];
+
+        let [a, f, b, c, d, e, h, l] = regs;
+        self.a = a; self.f = f; self.b = b; self.c = c;
+        self.d = d; self.e = e; self.h = h; self.l = l;
+        self.pc = pc; self.sp = sp;
+        self.halt = halt; self.ime = ime;
+        let ([gm, gt], [lm, lt]) = (global, last);
+        self.global_m = gm; self.global_t = gt;
+        self.last_m = lm; self.last_t = lt;
+        return Ok(());
+    }
+}
 
 static isa_map: [fn(&mut Z80); 256] = [
 
@@ -127,8 +359,8 @@ static isa_map: [fn(&mut Z80); 256] = [
         } else {
             cpu.f &= 0xff - CARRY_FLAG;
         }
-        cpu.h = (hl >> 8) as u8;
-        cpu.l = (hl & 0xff) as u8;
+        cpu.h = (i >> 8) as u8;
+        cpu.l = (i & 0xff) as u8;
         cpu.last_m = 3; cpu.last_t = 12;
     }, //ADDHLBC
     |cpu: &mut Z80| {
@@ -157,16 +389,13 @@ static isa_map: [fn(&mut Z80); 256] = [
 
     //10
     |cpu: &mut Z80| {
-        cpu.last_m = 2; cpu.last_t = 8;
-        let offset = mem_access_b!(cpu.memory_unit, cpu.pc) as i8;
+        //the byte after STOP is read and skipped like the operand of any two byte instruction
+        mem_access_b!(cpu.memory_unit, cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
-        cpu.b = cpu.b.wrapping_sub(1);
-        if cpu.b != 0 {
-            cpu.pc = (cpu.pc as i16).wrapping_add(offset as i16) as u16;
-            cpu.last_m += 1;
-            cpu.last_t += 4;
-        }
-    }, //DJNZn
+        //without an armed cgb speed switch, sleep until an interrupt like HALT
+        if !cpu.memory_unit.switch_speed() { cpu.halt = true; }
+        cpu.last_m = 1; cpu.last_t = 4;
+    }, //STOP
     |cpu: &mut Z80| {
         cpu.e = mem_access_b!(cpu.memory_unit, cpu.pc);
         cpu.d = mem_access_b!(cpu.memory_unit, cpu.pc.wrapping_add(1));
@@ -293,8 +522,8 @@ static isa_map: [fn(&mut Z80); 256] = [
         } else {
             cpu.f &= 0xff - CARRY_FLAG;
         }
-        cpu.h = (hl >> 8) as u8;
-        cpu.l = (hl & 0xff) as u8;
+        cpu.h = (i >> 8) as u8;
+        cpu.l = (i & 0xff) as u8;
         cpu.last_m = 3; cpu.last_t = 12;
     }, //ADDHLHL
     |cpu: &mut Z80| {
@@ -742,13 +971,13 @@ static isa_map: [fn(&mut Z80); 256] = [
     |cpu: &mut Z80| {
         let val = mem_access_b!(cpu.memory_unit, cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
-        let old = cpu.a;
-        cpu.a = cpu.a.wrapping_add(val);
-        if cpu.f & CARRY_FLAG != 0 {
-            cpu.a = cpu.a.wrapping_add(1);
-        }
+        let carry = (cpu.f & CARRY_FLAG != 0) as u8;
+        let sum = cpu.a as u16 + val as u16 + carry as u16;
+        let half = (cpu.a & 0xf) + (val & 0xf) + carry > 0xf;
+        cpu.a = sum as u8;
         cpu.f = 0;
-        if cpu.a <= old { cpu.f |= CARRY_FLAG; }
+        if sum > 0xff { cpu.f |= CARRY_FLAG; }
+        if half { cpu.f |= HCARRY_FLAG; }
         if cpu.a == 0 { cpu.f |= ZERO_FLAG; }
         cpu.last_m = 2; cpu.last_t = 8;
     }, //ADCn
@@ -781,10 +1010,11 @@ static isa_map: [fn(&mut Z80); 256] = [
     |cpu: &mut Z80| {
         cpu.last_m = 3; cpu.last_t = 12;
         if cpu.f & CARRY_FLAG == 0 {
-            cpu.pc = mem_access_w!(cpu.memory_unit, cpu.sp);
-            cpu.sp = cpu.sp.wrapping_add(2);
+            cpu.sp = cpu.sp.wrapping_sub(2);
+            mem_access_w!(cpu.memory_unit, cpu.sp, cpu.pc.wrapping_add(2));
+            cpu.pc = mem_access_w!(cpu.memory_unit, cpu.pc);
             cpu.last_m += 2; cpu.last_t += 8;
-        }
+        } else { cpu.pc = cpu.pc.wrapping_add(2); }
     }, //CALLNCnn
     |cpu: &mut Z80| {
         cpu.sp = cpu.sp.wrapping_sub(2);
@@ -280,7 +280,7 @@ macro_rules! SWAPr_x {
 #[macro_export]
 macro_rules! undefined {
     () => {
-        |cpu: &mut Z80| { panic!("Hit undefined instruction at {:?}", stringify!(cpu.pc - 1)); }
+        |cpu: &mut Z80| { panic!("Hit undefined instruction at {:#06x}", cpu.pc.wrapping_sub(1)); }
     }
 }
 
@@ -0,0 +1,36 @@
+/*
+the crc32 used by zip, png and the ups/bps patch formats (polynomial
+0xedb88320, reflected, starting from and finishing with 0xffffffff)
+*/
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    return table;
+}
+
+static TABLE: [u32; 256] = make_table();
+
+// continues a crc over more data, start from crc32(&[]) (which is 0)
+pub fn update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for b in data {
+        crc = TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    return !crc;
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    return update(0, data);
+}
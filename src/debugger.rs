@@ -0,0 +1,412 @@
+/*
+interactive debugger, driven one instruction at a time through Z80::run.
+breakpoints are checked against pc before each dispatch and watchpoints
+are reported by the MMU once the instruction that touched them finishes
+
+numbers are hex, with or without a $ or 0x prefix. with symbols loaded
+addresses can also be given as labels, and labels are shown in the
+disassembly and wherever the debugger reports an address
+*/
+use std::io::{BufRead, Result, Write};
+
+use crate::cpu::Z80;
+use crate::disasm;
+use crate::mmu::{Access, Watchpoint};
+use crate::search::{Filter, Search, Width};
+use crate::symbols::Symbols;
+
+const HELP: &str = "\
+commands:
+  s, step [n]            execute n instructions (default 1)
+  n, next                step over a CALL or RST
+  finish                 run until the current function returns
+  c, continue            run until a breakpoint or watchpoint
+  limit [n]              show or set how many instructions c, n and finish run at most
+  b, break <addr>        set a breakpoint, addr can be a label
+  d, delete <addr>       remove a breakpoint
+  watch <r|w|rw> <addr> [end]  stop on reads and/or writes
+  unwatch <addr>         remove watchpoints covering addr
+  i, info                list breakpoints and watchpoints
+  r, regs                show registers and flags
+  dis [addr] [n]         disassemble n instructions (default pc, 10)
+  set <reg> <value>      change a register or flag (zf, nf, hf, cf)
+  x <addr> [len]         hexdump memory
+  w <addr> <byte>        write a byte to memory
+  cheat [add <code>|on <n>|off <n>|rm <n>]  list or change cheat codes
+  search new [8|16]      start a ram search over every address
+  search <filter>        keep addresses that are same, changed, inc, dec, = <v> or != <v>
+  search list [n]        show up to n addresses left (default 20)
+  q, quit                leave the debugger";
+
+const CALL_OPS: [u8; 5] = [0xc4, 0xcc, 0xcd, 0xd4, 0xdc];
+const RET_OPS: [u8; 6] = [0xc0, 0xc8, 0xc9, 0xd0, 0xd8, 0xd9];
+
+// about ten seconds of game time, so a program that never hits a
+// breakpoint still hands control back
+pub const STEP_LIMIT: u32 = 10_000_000;
+
+pub enum Stop {
+    Stepped,
+    Breakpoint(u16),
+    Watchpoint { pc: u16, address: u16, access: Access, value: u8 },
+    Returned,
+    // ran the step limit without stopping
+    Limit(u32)
+}
+
+pub struct Debugger {
+    breakpoints: Vec<u16>,
+    search: Option<Search>,
+    symbols: Option<Symbols>,
+    step_limit: u32
+}
+
+pub fn parse_number(text: &str) -> Option<u16> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    return u16::from_str_radix(digits, 16).ok();
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        return Debugger { breakpoints: Vec::new(), search: None, symbols: None, step_limit: STEP_LIMIT };
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    // a label or a hex number
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        let label = self.symbols.as_ref().and_then(|s| s.lookup(text));
+        return label.map(|(_, address)| address).or_else(|| parse_number(text));
+    }
+
+    // the label at `address` in the banks mapped now
+    fn label(&self, cpu: &Z80, address: u16) -> Option<&str> {
+        return self.symbols.as_ref()?.label(cpu.memory_unit.bank_at(address), address);
+    }
+
+    // "0150" or "0150 (Main)"
+    fn describe(&self, cpu: &Z80, address: u16) -> String {
+        return match self.label(cpu, address) {
+            Some(name) => format!("{:04X} ({})", address, name),
+            None => format!("{:04X}", address)
+        };
+    }
+
+    pub fn set_step_limit(&mut self, limit: u32) {
+        self.step_limit = limit.max(1);
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|b| *b != address);
+        return before != self.breakpoints.len();
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        return &self.breakpoints;
+    }
+
+    // runs one instruction, reporting a watchpoint it tripped
+    pub fn step(&mut self, cpu: &mut Z80) -> Stop {
+        let pc = cpu.pc;
+        cpu.run();
+        return match cpu.memory_unit.take_watch_hit() {
+            Some(hit) => Stop::Watchpoint { pc, address: hit.address, access: hit.access, value: hit.value },
+            None => Stop::Stepped
+        };
+    }
+
+    // keeps stepping until `done` says so, a breakpoint or watchpoint stops
+    // us or the step limit runs out
+    fn run_until<F: FnMut(&Z80, u8) -> bool>(&mut self, cpu: &mut Z80, mut done: F) -> Stop {
+        for _ in 0..self.step_limit {
+            let op = cpu.memory_unit.peek(cpu.pc);
+            if let Stop::Watchpoint { pc, address, access, value } = self.step(cpu) {
+                return Stop::Watchpoint { pc, address, access, value };
+            }
+            if done(cpu, op) {
+                return Stop::Returned;
+            }
+            if self.breakpoints.contains(&cpu.pc) {
+                return Stop::Breakpoint(cpu.pc);
+            }
+        }
+        return Stop::Limit(self.step_limit);
+    }
+
+    pub fn continue_(&mut self, cpu: &mut Z80) -> Stop {
+        return self.run_until(cpu, |_, _| false);
+    }
+
+    pub fn step_over(&mut self, cpu: &mut Z80) -> Stop {
+        let op = cpu.memory_unit.peek(cpu.pc);
+        let len = if CALL_OPS.contains(&op) { 3 } else if op & 0xc7 == 0xc7 { 1 } else { 0 };
+        if len == 0 {
+            return self.step(cpu);
+        }
+        let target = cpu.pc.wrapping_add(len);
+        let sp = cpu.sp;
+        return match self.run_until(cpu, |c, _| c.pc == target && c.sp >= sp) {
+            Stop::Returned => Stop::Stepped,
+            stop => stop
+        };
+    }
+
+    pub fn finish(&mut self, cpu: &mut Z80) -> Stop {
+        let sp = cpu.sp;
+        return self.run_until(cpu, |c, op| RET_OPS.contains(&op) && c.sp > sp);
+    }
+
+    pub fn print_registers<W: Write>(&self, cpu: &Z80, out: &mut W) -> Result<()> {
+        let flag = |mask: u8, name: char| if cpu.f & mask != 0 { name } else { '-' };
+        writeln!(out, "AF={:02X}{:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X} PC={:04X}",
+            cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, cpu.pc)?;
+        writeln!(out, "flags={}{}{}{} ime={} halt={}",
+            flag(0x80, 'Z'), flag(0x40, 'N'), flag(0x20, 'H'), flag(0x10, 'C'), cpu.ime as u8, cpu.halt as u8)?;
+        return Ok(());
+    }
+
+    pub fn hexdump<W: Write>(&self, cpu: &Z80, start: u16, len: u16, out: &mut W) -> Result<()> {
+        let mut address = start;
+        let mut left = len as u32;
+        while left > 0 {
+            let count = left.min(16) as u16;
+            let bytes: Vec<u8> = (0..count).map(|i| cpu.memory_unit.peek(address.wrapping_add(i))).collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let ascii: String = bytes.iter()
+                .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+                .collect();
+            writeln!(out, "{:04X}: {:<47} |{}|", address, hex.join(" "), ascii)?;
+            address = address.wrapping_add(count);
+            left -= count as u32;
+        }
+        return Ok(());
+    }
+
+    fn report<W: Write>(&self, cpu: &Z80, stop: Stop, out: &mut W) -> Result<()> {
+        match stop {
+            Stop::Stepped | Stop::Returned => {},
+            Stop::Breakpoint(address) => writeln!(out, "breakpoint at {}", self.describe(cpu, address))?,
+            Stop::Limit(count) => writeln!(out, "stopped after {} instructions", count)?,
+            Stop::Watchpoint { pc, address, access, value } => {
+                let kind = match access {
+                    Access::Read | Access::Dma => "read",
+                    Access::Write => "write",
+                    Access::Execute => "execute"
+                };
+                writeln!(out, "watchpoint {} at {} (value {:02X}) by instruction at {}",
+                    kind, self.describe(cpu, address), value, self.describe(cpu, pc))?;
+            }
+        }
+        self.print_registers(cpu, out)?;
+        return self.disassemble(cpu, cpu.pc, 1, out);
+    }
+
+    pub fn disassemble<W: Write>(&self, cpu: &Z80, start: u16, count: u16, out: &mut W) -> Result<()> {
+        let mut address = start;
+        for _ in 0..count {
+            let instruction = disasm::decode(|a| cpu.memory_unit.peek(a), address);
+            let marker = if self.breakpoints.contains(&address) { '*' } else { ' ' };
+            if let Some(name) = self.label(cpu, address) { writeln!(out, "{}:", name)?; }
+            writeln!(out, "{}{}", marker, instruction.listing_with(|target| self.label(cpu, target).map(String::from)))?;
+            address = address.wrapping_add(instruction.len() as u16);
+        }
+        return Ok(());
+    }
+
+    // runs the command loop until quit or end of input
+    pub fn repl<R: BufRead, W: Write>(&mut self, cpu: &mut Z80, input: R, out: &mut W) -> Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(gbdb) ")?;
+            out.flush()?;
+            let line = match lines.next() {
+                None => return Ok(()),
+                Some(line) => line?
+            };
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if args.is_empty() { continue; }
+
+            if !self.command(cpu, &args, out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // cheat [add <code> | on <n> | off <n> | rm <n>], lists the codes without arguments
+    fn cheat<W: Write>(&mut self, cpu: &mut Z80, args: &[&str], out: &mut W) -> Result<()> {
+        let cheats = &mut cpu.memory_unit.cheats;
+        let index = args.get(1).and_then(|a| a.parse::<usize>().ok());
+        match (args.first().copied(), index) {
+            (None, _) => {
+                for (i, cheat) in cheats.cheats().iter().enumerate() {
+                    writeln!(out, "{:2} {:<12} {}", i, cheat.text, if cheat.enabled { "on" } else { "off" })?;
+                }
+            },
+            (Some("add"), _) if args.len() == 2 => match cheats.add(args[1]) {
+                Ok(i) => writeln!(out, "{:2} {}", i, cheats.cheats()[i].text)?,
+                Err(e) => writeln!(out, "{}", e)?
+            },
+            (Some("on"), Some(i)) | (Some("off"), Some(i)) => {
+                if !cheats.set_enabled(i, args[0] == "on") { writeln!(out, "no cheat {}", i)?; }
+            },
+            (Some("rm"), Some(i)) => {
+                if cheats.remove(i).is_none() { writeln!(out, "no cheat {}", i)?; }
+            },
+            _ => writeln!(out, "usage: cheat [add <code> | on <n> | off <n> | rm <n>]")?
+        }
+        return Ok(());
+    }
+
+    fn search<W: Write>(&mut self, cpu: &Z80, args: &[&str], out: &mut W) -> Result<()> {
+        let value = args.get(1).and_then(|a| parse_number(a));
+        let filter = match (args.first().copied(), value) {
+            (Some("new"), _) => {
+                let width = if args.get(1) == Some(&"16") { Width::Word } else { Width::Byte };
+                let search = Search::new(&cpu.memory_unit, width);
+                writeln!(out, "{} addresses", search.len())?;
+                self.search = Some(search);
+                return Ok(());
+            },
+            (Some("list"), _) => {
+                let count = args.get(1).and_then(|a| a.parse::<usize>().ok()).unwrap_or(20);
+                let search = match &self.search {
+                    Some(search) => search,
+                    None => return writeln!(out, "no search, start one with search new")
+                };
+                for (address, value) in search.results().iter().take(count) {
+                    match search.width() {
+                        Width::Byte => writeln!(out, "{:04X}  {:02X}", address, value)?,
+                        Width::Word => writeln!(out, "{:04X}  {:04X}", address, value)?
+                    }
+                }
+                return writeln!(out, "{} addresses", search.len());
+            },
+            (Some("same"), _) => Filter::Unchanged,
+            (Some("changed"), _) => Filter::Changed,
+            (Some("inc"), _) => Filter::Increased,
+            (Some("dec"), _) => Filter::Decreased,
+            (Some("="), Some(v)) => Filter::Equal(v),
+            (Some("!="), Some(v)) => Filter::NotEqual(v),
+            _ => return writeln!(out, "usage: search new [8|16] | same | changed | inc | dec | = <v> | != <v> | list [n]")
+        };
+        match self.search.as_mut() {
+            Some(search) => writeln!(out, "{} addresses", search.filter(&cpu.memory_unit, filter))?,
+            None => writeln!(out, "no search, start one with search new")?
+        }
+        return Ok(());
+    }
+
+    // executes one command, returns false when the user asked to quit
+    pub fn command<W: Write>(&mut self, cpu: &mut Z80, args: &[&str], out: &mut W) -> Result<bool> {
+        let number = |i: usize| args.get(i).and_then(|a| parse_number(a));
+        let address = |i: usize| args.get(i).and_then(|a| self.resolve(a));
+        match args[0] {
+            "s" | "step" => {
+                let count = args.get(1).and_then(|a| a.parse::<u32>().ok()).unwrap_or(1);
+                let mut stop = Stop::Stepped;
+                for _ in 0..count {
+                    stop = self.step(cpu);
+                    if let Stop::Watchpoint { .. } = stop { break; }
+                }
+                self.report(cpu, stop, out)?;
+            },
+            "n" | "next" => {
+                let stop = self.step_over(cpu);
+                self.report(cpu, stop, out)?;
+            },
+            "finish" => {
+                let stop = self.finish(cpu);
+                self.report(cpu, stop, out)?;
+            },
+            "limit" => match args.get(1).and_then(|a| a.parse::<u32>().ok()) {
+                Some(limit) => self.set_step_limit(limit),
+                None => writeln!(out, "{} instructions", self.step_limit)?
+            },
+            "c" | "continue" => {
+                let stop = self.continue_(cpu);
+                self.report(cpu, stop, out)?;
+            },
+            "b" | "break" => match address(1) {
+                Some(address) => {
+                    self.add_breakpoint(address);
+                    writeln!(out, "breakpoint at {}", self.describe(cpu, address))?;
+                },
+                None => writeln!(out, "usage: break <addr>")?
+            },
+            "d" | "delete" => match address(1) {
+                Some(address) if self.remove_breakpoint(address) => writeln!(out, "deleted {:04X}", address)?,
+                Some(address) => writeln!(out, "no breakpoint at {:04X}", address)?,
+                None => writeln!(out, "usage: delete <addr>")?
+            },
+            "watch" => {
+                let kinds = args.get(1).copied().unwrap_or("");
+                match (address(2), kinds) {
+                    (Some(start), "r") | (Some(start), "w") | (Some(start), "rw") => {
+                        let end = address(3).unwrap_or(start);
+                        cpu.memory_unit.add_watchpoint(Watchpoint {
+                            start, end, read: kinds.contains('r'), write: kinds.contains('w')
+                        });
+                        writeln!(out, "watching {:04X}-{:04X} ({})", start, end, kinds)?;
+                    },
+                    _ => writeln!(out, "usage: watch <r|w|rw> <addr> [end]")?
+                }
+            },
+            "unwatch" => match address(1) {
+                Some(address) => {
+                    let removed = cpu.memory_unit.remove_watchpoint(address);
+                    writeln!(out, "removed {} watchpoint(s)", removed)?;
+                },
+                None => writeln!(out, "usage: unwatch <addr>")?
+            },
+            "i" | "info" => {
+                for b in self.breakpoints.iter() {
+                    writeln!(out, "break {}", self.describe(cpu, *b))?;
+                }
+                for w in cpu.memory_unit.watchpoints() {
+                    let kinds = format!("{}{}", if w.read { "r" } else { "" }, if w.write { "w" } else { "" });
+                    writeln!(out, "watch {:04X}-{:04X} {}", w.start, w.end, kinds)?;
+                }
+            },
+            "r" | "regs" => self.print_registers(cpu, out)?,
+            "dis" => {
+                let start = address(1).unwrap_or(cpu.pc);
+                self.disassemble(cpu, start, number(2).unwrap_or(10), out)?;
+            },
+            "set" => match (args.get(1), number(2)) {
+                (Some(reg), Some(value)) if cpu.set_register(reg, value) => self.print_registers(cpu, out)?,
+                (Some(reg), Some(_)) => writeln!(out, "unknown register {}", reg)?,
+                _ => writeln!(out, "usage: set <reg> <value>")?
+            },
+            "x" => match address(1) {
+                Some(address) => self.hexdump(cpu, address, number(2).unwrap_or(0x40), out)?,
+                None => writeln!(out, "usage: x <addr> [len]")?
+            },
+            "w" => match (address(1), number(2)) {
+                // the debugger's write is not the program's, no watchpoint or hook sees it
+                (Some(address), Some(value)) => cpu.memory_unit.poke(address, value as u8),
+                _ => writeln!(out, "usage: w <addr> <byte>")?
+            },
+            "cheat" => self.cheat(cpu, &args[1..], out)?,
+            "search" => self.search(cpu, &args[1..], out)?,
+            "q" | "quit" => return Ok(false),
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+            other => writeln!(out, "unknown command {}, try help", other)?
+        }
+        return Ok(true);
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        return Debugger::new();
+    }
+}
@@ -0,0 +1,239 @@
+/*
+LR35902 disassembler. operands in the templates below are filled in from
+the bytes following the opcode:
+
+    d8 / d16   immediate data
+    a8         high page address ($FF00 + n)
+    a16        absolute address
+    r8         relative jump, shown as its target
+    s8         signed offset added to sp
+
+with symbols the a8, a16 and r8 targets that have a label show it instead
+*/
+use crate::symbols::Symbols;
+
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    template: String
+}
+
+static templates: [&str; 256] = [
+
+    //00
+    "NOP", "LD BC,d16", "LD (BC),A", "INC BC", "INC B", "DEC B", "LD B,d8", "RLCA",
+    "LD (a16),SP", "ADD HL,BC", "LD A,(BC)", "DEC BC", "INC C", "DEC C", "LD C,d8", "RRCA",
+
+    //10
+    "STOP", "LD DE,d16", "LD (DE),A", "INC DE", "INC D", "DEC D", "LD D,d8", "RLA",
+    "JR r8", "ADD HL,DE", "LD A,(DE)", "DEC DE", "INC E", "DEC E", "LD E,d8", "RRA",
+
+    //20
+    "JR NZ,r8", "LD HL,d16", "LD (HL+),A", "INC HL", "INC H", "DEC H", "LD H,d8", "DAA",
+    "JR Z,r8", "ADD HL,HL", "LD A,(HL+)", "DEC HL", "INC L", "DEC L", "LD L,d8", "CPL",
+
+    //30
+    "JR NC,r8", "LD SP,d16", "LD (HL-),A", "INC SP", "INC (HL)", "DEC (HL)", "LD (HL),d8", "SCF",
+    "JR C,r8", "ADD HL,SP", "LD A,(HL-)", "DEC SP", "INC A", "DEC A", "LD A,d8", "CCF",
+
+    //40
+    "LD B,B", "LD B,C", "LD B,D", "LD B,E", "LD B,H", "LD B,L", "LD B,(HL)", "LD B,A",
+    "LD C,B", "LD C,C", "LD C,D", "LD C,E", "LD C,H", "LD C,L", "LD C,(HL)", "LD C,A",
+
+    //50
+    "LD D,B", "LD D,C", "LD D,D", "LD D,E", "LD D,H", "LD D,L", "LD D,(HL)", "LD D,A",
+    "LD E,B", "LD E,C", "LD E,D", "LD E,E", "LD E,H", "LD E,L", "LD E,(HL)", "LD E,A",
+
+    //60
+    "LD H,B", "LD H,C", "LD H,D", "LD H,E", "LD H,H", "LD H,L", "LD H,(HL)", "LD H,A",
+    "LD L,B", "LD L,C", "LD L,D", "LD L,E", "LD L,H", "LD L,L", "LD L,(HL)", "LD L,A",
+
+    //70
+    "LD (HL),B", "LD (HL),C", "LD (HL),D", "LD (HL),E", "LD (HL),H", "LD (HL),L", "HALT", "LD (HL),A",
+    "LD A,B", "LD A,C", "LD A,D", "LD A,E", "LD A,H", "LD A,L", "LD A,(HL)", "LD A,A",
+
+    //80
+    "ADD A,B", "ADD A,C", "ADD A,D", "ADD A,E", "ADD A,H", "ADD A,L", "ADD A,(HL)", "ADD A,A",
+    "ADC A,B", "ADC A,C", "ADC A,D", "ADC A,E", "ADC A,H", "ADC A,L", "ADC A,(HL)", "ADC A,A",
+
+    //90
+    "SUB B", "SUB C", "SUB D", "SUB E", "SUB H", "SUB L", "SUB (HL)", "SUB A",
+    "SBC A,B", "SBC A,C", "SBC A,D", "SBC A,E", "SBC A,H", "SBC A,L", "SBC A,(HL)", "SBC A,A",
+
+    //a0
+    "AND B", "AND C", "AND D", "AND E", "AND H", "AND L", "AND (HL)", "AND A",
+    "XOR B", "XOR C", "XOR D", "XOR E", "XOR H", "XOR L", "XOR (HL)", "XOR A",
+
+    //b0
+    "OR B", "OR C", "OR D", "OR E", "OR H", "OR L", "OR (HL)", "OR A",
+    "CP B", "CP C", "CP D", "CP E", "CP H", "CP L", "CP (HL)", "CP A",
+
+    //c0
+    "RET NZ", "POP BC", "JP NZ,a16", "JP a16", "CALL NZ,a16", "PUSH BC", "ADD A,d8", "RST $00",
+    "RET Z", "RET", "JP Z,a16", "PREFIX CB", "CALL Z,a16", "CALL a16", "ADC A,d8", "RST $08",
+
+    //d0
+    "RET NC", "POP DE", "JP NC,a16", "", "CALL NC,a16", "PUSH DE", "SUB d8", "RST $10",
+    "RET C", "RETI", "JP C,a16", "", "CALL C,a16", "", "SBC A,d8", "RST $18",
+
+    //e0
+    "LDH (a8),A", "POP HL", "LD (C),A", "", "", "PUSH HL", "AND d8", "RST $20",
+    "ADD SP,s8", "JP (HL)", "LD (a16),A", "", "", "", "XOR d8", "RST $28",
+
+    //f0
+    "LDH A,(a8)", "POP AF", "LD A,(C)", "DI", "", "PUSH AF", "OR d8", "RST $30",
+    "LD HL,SP+s8", "LD SP,HL", "LD A,(a16)", "EI", "", "", "CP d8", "RST $38"
+
+];
+
+static cb_ops: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+static cb_regs: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+
+fn cb_template(op: u8) -> String {
+    let reg = cb_regs[(op & 7) as usize];
+    let bit = (op >> 3) & 7;
+    return match op >> 6 {
+        0 => format!("{} {}", cb_ops[bit as usize], reg),
+        1 => format!("BIT {},{}", bit, reg),
+        2 => format!("RES {},{}", bit, reg),
+        _ => format!("SET {},{}", bit, reg)
+    };
+}
+
+// number of bytes taken by the instruction starting with `op`
+pub fn instruction_length(op: u8) -> u8 {
+    let template = templates[op as usize];
+    if op == 0xcb || op == 0x10 { return 2; }
+    if template.contains("16") { return 3; }
+    if template.contains('8') && !template.starts_with("RST") { return 2; }
+    return 1;
+}
+
+// decodes the instruction at `address`, reading memory through `read`
+pub fn decode<F: Fn(u16) -> u8>(read: F, address: u16) -> Instruction {
+    let op = read(address);
+    let len = instruction_length(op);
+    let bytes: Vec<u8> = (0..len as u16).map(|i| read(address.wrapping_add(i))).collect();
+
+    let template = if op == 0xcb {
+        cb_template(bytes[1])
+    } else if templates[op as usize].is_empty() {
+        format!("DB ${:02X}", op)
+    } else {
+        templates[op as usize].to_string()
+    };
+    return Instruction { address, bytes, template };
+}
+
+impl Instruction {
+    pub fn len(&self) -> u8 {
+        return self.bytes.len() as u8;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.bytes.is_empty();
+    }
+
+    fn imm16(&self) -> u16 {
+        return ((self.bytes[2] as u16) << 8) | self.bytes[1] as u16;
+    }
+
+    fn relative_target(&self) -> u16 {
+        let offset = self.bytes[1] as i8 as i16 as u16;
+        return self.address.wrapping_add(self.len() as u16).wrapping_add(offset);
+    }
+
+    // the memory address named by the operand, if it has one
+    pub fn target(&self) -> Option<u16> {
+        if self.template.contains("a16") { return Some(self.imm16()); }
+        if self.template.contains("a8") { return Some(0xff00 | self.bytes[1] as u16); }
+        if self.template.contains("r8") { return Some(self.relative_target()); }
+        if self.template.starts_with("RST") { return Some((self.bytes[0] & 0x38) as u16); }
+        return None;
+    }
+
+    pub fn mnemonic(&self) -> String {
+        return self.mnemonic_with(|_| None);
+    }
+
+    // the mnemonic with the target named by `label` when it has a name
+    pub fn mnemonic_with<F: Fn(u16) -> Option<String>>(&self, label: F) -> String {
+        let mut text = self.template.clone();
+        if text.contains("d16") {
+            text = text.replace("d16", &format!("${:04X}", self.imm16()));
+        } else if text.contains("d8") {
+            text = text.replace("d8", &format!("${:02X}", self.bytes[1]));
+        } else if text.contains("s8") {
+            let offset = self.bytes[1] as i8;
+            let magnitude = format!("${:02X}", (offset as i16).abs());
+            if offset < 0 {
+                let negative = format!("-{}", magnitude);
+                text = text.replace("+s8", &negative).replace("s8", &negative);
+            } else {
+                text = text.replace("s8", &magnitude);
+            }
+        } else if let Some(target) = self.target() {
+            let text_target = label(target).unwrap_or_else(|| format!("${:04X}", target));
+            text = text.replace("a16", &text_target).replace("a8", &text_target).replace("r8", &text_target);
+        }
+        return text;
+    }
+
+    // "0150  C3 50 01  JP $0150"
+    pub fn listing(&self) -> String {
+        return self.listing_with(|_| None);
+    }
+
+    // "0150  C3 50 01  JP Main", see mnemonic_with
+    pub fn listing_with<F: Fn(u16) -> Option<String>>(&self, label: F) -> String {
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        return format!("{:04X}  {:<9} {}", self.address, hex.join(" "), self.mnemonic_with(label));
+    }
+}
+
+// rom offsets map to bank 0 at 0000-3fff and every later bank at 4000-7fff
+pub fn rom_address(offset: usize) -> (usize, u16) {
+    let bank = offset / 0x4000;
+    let address = (offset % 0x4000) as u16 + if bank == 0 { 0 } else { 0x4000 };
+    return (bank, address);
+}
+
+// the bank an address in code from `rom_bank` sees, what MMU::bank_at
+// would say while that bank is mapped
+fn bank_seen_from(rom_bank: usize, address: u16) -> u16 {
+    return match address {
+        0x4000..=0x7fff => rom_bank.max(1) as u16,
+        0xd000..=0xdfff => 1,
+        _ => 0
+    };
+}
+
+// disassembles rom[start..end] linearly, one line per instruction
+pub fn disassemble_rom(rom: &[u8], start: usize, end: usize) -> Vec<String> {
+    return disassemble_rom_with(rom, start, end, None);
+}
+
+// disassembles with labels from `symbols` on their own lines and in place of targets
+pub fn disassemble_rom_with(rom: &[u8], start: usize, end: usize, symbols: Option<&Symbols>) -> Vec<String> {
+    let end = end.min(rom.len());
+    let mut lines = Vec::new();
+    let mut offset = start;
+    while offset < end {
+        let (bank, address) = rom_address(offset);
+        let read = |a: u16| {
+            let i = offset + a.wrapping_sub(address) as usize;
+            if i < rom.len() { rom[i] } else { 0xff }
+        };
+        let instruction = decode(read, address);
+        match symbols {
+            Some(symbols) => {
+                if let Some(name) = symbols.label(bank as u16, address) { lines.push(format!("{}:", name)); }
+                let label = |target| symbols.label(bank_seen_from(bank, target), target).map(String::from);
+                lines.push(format!("{:02X}:{}", bank, instruction.listing_with(label)));
+            },
+            None => lines.push(format!("{:02X}:{}", bank, instruction.listing()))
+        }
+        offset += instruction.len() as usize;
+    }
+    return lines;
+}
@@ -0,0 +1,341 @@
+/*
+gdb remote serial protocol stub, so gdb or an ide built on it can drive
+the cpu over tcp (target remote localhost:<port>). packets are
+$<data>#<two hex digit checksum>, each acknowledged with + (or - to ask
+for it again) until the client turns acks off with QStartNoAckMode
+
+    ?                 why the cpu stopped
+    g / G             read / write every register
+    p n / P n=v       read / write one register
+    m addr,len        read memory
+    M addr,len:data   write memory
+    c [addr]          continue, stopping at a breakpoint, a watchpoint or ctrl-c
+    s [addr]          single step
+    Z0-Z1 / z0-z1     set / clear a breakpoint
+    Z2-Z4 / z2-z4     set / clear a write, read or access watchpoint
+    D / k             detach / kill, ending the session
+
+gdb has no game boy architecture so the registers are described to it
+with a target.xml: af, bc, de, hl, sp and pc, 16 bits each, little
+endian. memory is the cpu's view of the bus through MMU::peek and
+MMU::poke, which trip no watchpoints or hooks, though writes to
+registers act as the cpu's would (0xff55 starts a vram dma, 0xff50 unmaps
+the boot rom). anything else gets the empty reply that tells gdb
+a packet is not supported
+*/
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::cpu::Z80;
+use crate::debugger::{Debugger, Stop};
+use crate::mmu::{Access, Watchpoint};
+
+pub const REGISTERS: [&str; 6] = ["af", "bc", "de", "hl", "sp", "pc"];
+
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\"><feature name=\"org.gnu.gdb.sm83.cpu\">\
+<reg name=\"af\" bitsize=\"16\" type=\"int\" regnum=\"0\"/>\
+<reg name=\"bc\" bitsize=\"16\" type=\"int\"/>\
+<reg name=\"de\" bitsize=\"16\" type=\"int\"/>\
+<reg name=\"hl\" bitsize=\"16\" type=\"int\"/>\
+<reg name=\"sp\" bitsize=\"16\" type=\"data_ptr\"/>\
+<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+</feature></target>";
+
+// instructions run between looks at the socket for a ctrl-c
+const INTERRUPT_CHECK: u32 = 4096;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+pub struct Server {
+    listener: TcpListener
+}
+
+impl Server {
+    // listens on localhost, port 0 picks a free one
+    pub fn bind(port: u16) -> Result<Server> {
+        return Ok(Server { listener: TcpListener::bind(("127.0.0.1", port))? });
+    }
+
+    pub fn port(&self) -> Result<u16> {
+        return Ok(self.listener.local_addr()?.port());
+    }
+
+    // waits for gdb to connect and serves it until it detaches or hangs up
+    pub fn serve(&self, cpu: &mut Z80) -> Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        let mut session = Session { stream, debugger: Debugger::new(), acks: true, stop: format!("S{:02x}", SIGTRAP) };
+        return session.run(cpu);
+    }
+}
+
+fn hex_byte(text: &str) -> Option<u8> {
+    return u8::from_str_radix(text.get(..2)?, 16).ok();
+}
+
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) { return None; }
+    return (0..text.len()).step_by(2).map(|i| hex_byte(&text[i..])).collect();
+}
+
+fn hex_number(text: &str) -> Option<u16> {
+    return u16::from_str_radix(text, 16).ok();
+}
+
+// "addr,len" as used by m, M, Z and z
+fn address_length(text: &str) -> Option<(u16, u16)> {
+    let (address, length) = text.split_once(',')?;
+    return Some((hex_number(address)?, hex_number(length)?));
+}
+
+fn little_endian(value: u16) -> String {
+    return format!("{:02x}{:02x}", value & 0xff, value >> 8);
+}
+
+struct Session {
+    stream: TcpStream,
+    debugger: Debugger,
+    acks: bool,
+    // the last stop reply, for ?
+    stop: String
+}
+
+impl Session {
+    fn run(&mut self, cpu: &mut Z80) -> Result<()> {
+        while let Some(packet) = self.receive()? {
+            // kill expects no reply
+            if packet == "k" { return Ok(()); }
+            let reply = match self.command(cpu, &packet)? {
+                Some(reply) => reply,
+                None => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+            };
+            self.send(&reply)?;
+        }
+        return Ok(());
+    }
+
+    fn byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0];
+        return match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0]))
+        };
+    }
+
+    // the next packet's data, None once gdb hangs up
+    fn receive(&mut self) -> Result<Option<String>> {
+        loop {
+            // anything before the $ is an ack or a stray ctrl-c from a race with a stop
+            match self.byte()? {
+                None => return Ok(None),
+                Some(b'$') => {},
+                Some(_) => continue
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => data.push(b)
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(hex_byte);
+            let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            if expected != Some(sum) {
+                if self.acks { self.stream.write_all(b"-")?; }
+                continue;
+            }
+            if self.acks { self.stream.write_all(b"+")?; }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.stream.write_all(format!("${}#{:02x}", data, sum).as_bytes())?;
+        self.stream.flush()?;
+        if !self.acks { return Ok(()); }
+        // gdb acks with + or asks again with -
+        loop {
+            match self.byte()? {
+                Some(b'+') | None => return Ok(()),
+                Some(b'-') => self.stream.write_all(format!("${}#{:02x}", data, sum).as_bytes())?,
+                Some(_) => {}
+            }
+        }
+    }
+
+    // whether gdb sent a ctrl-c while the cpu was running
+    fn interrupted(&mut self) -> Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        return match result {
+            Ok(1) => Ok(byte[0] == 0x03),
+            // gdb went away, stop so the session ends at the next read
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e)
+        };
+    }
+
+    // the reply to a packet, None to end the session
+    fn command(&mut self, cpu: &mut Z80, packet: &str) -> Result<Option<String>> {
+        let kind = packet.get(..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+        let error = String::from("E01");
+        let reply = match kind {
+            "?" => self.stop.clone(),
+            "g" => REGISTERS.iter().map(|r| little_endian(cpu.register(r).unwrap())).collect(),
+            "G" => match hex_bytes(args).filter(|b| b.len() == REGISTERS.len() * 2) {
+                Some(bytes) => {
+                    for (i, r) in REGISTERS.iter().enumerate() {
+                        cpu.set_register(r, bytes[i * 2] as u16 | ((bytes[i * 2 + 1] as u16) << 8));
+                    }
+                    String::from("OK")
+                },
+                None => error
+            },
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(|i| REGISTERS.get(i)) {
+                Some(r) => little_endian(cpu.register(r).unwrap()),
+                None => error
+            },
+            "P" => {
+                let set = args.split_once('=').and_then(|(i, value)| {
+                    let register = REGISTERS.get(usize::from_str_radix(i, 16).ok()?)?;
+                    let bytes = hex_bytes(value).filter(|b| b.len() == 2)?;
+                    return Some(cpu.set_register(register, bytes[0] as u16 | ((bytes[1] as u16) << 8)));
+                });
+                if set.is_some() { String::from("OK") } else { error }
+            },
+            "m" => match address_length(args) {
+                Some((address, length)) => (0..length)
+                    .map(|i| format!("{:02x}", cpu.memory().peek(address.wrapping_add(i))))
+                    .collect(),
+                None => error
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = address_length(range)?;
+                    return hex_bytes(data).filter(|d| d.len() == length as usize).map(|d| (address, d));
+                });
+                match write {
+                    Some((address, data)) => {
+                        for (i, value) in data.iter().enumerate() {
+                            cpu.memory_mut().poke(address.wrapping_add(i as u16), *value);
+                        }
+                        String::from("OK")
+                    },
+                    None => error
+                }
+            },
+            "c" | "s" => {
+                if let Some(address) = hex_number(args) { cpu.set_register("pc", address); }
+                let reply = self.resume(cpu, kind == "s")?;
+                self.stop = reply.clone();
+                reply
+            },
+            "Z" | "z" => self.breakpoint(cpu, kind == "Z", args).unwrap_or(error),
+            "H" | "T" => String::from("OK"),
+            "q" | "Q" => self.query(args),
+            "D" => return Ok(None),
+            _ => String::new()
+        };
+        return Ok(Some(reply));
+    }
+
+    fn query(&mut self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return String::from("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+");
+        }
+        if query == "StartNoAckMode" {
+            self.acks = false;
+            return String::from("OK");
+        }
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, length) = match range.split_once(',') {
+                Some((offset, length)) => (usize::from_str_radix(offset, 16), usize::from_str_radix(length, 16)),
+                None => return String::from("E01")
+            };
+            let (offset, length) = match (offset, length) {
+                (Ok(offset), Ok(length)) => (offset.min(TARGET_XML.len()), length),
+                _ => return String::from("E01")
+            };
+            let end = (offset + length).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { "m" } else { "l" };
+            return format!("{}{}", more, &TARGET_XML[offset..end]);
+        }
+        return match query {
+            "Attached" => String::from("1"),
+            "C" => String::from("QC1"),
+            "fThreadInfo" => String::from("m1"),
+            "sThreadInfo" => String::from("l"),
+            _ => String::new()
+        };
+    }
+
+    // Z or z type,addr,kind
+    fn breakpoint(&mut self, cpu: &mut Z80, set: bool, args: &str) -> Option<String> {
+        let (kind, rest) = args.split_once(',')?;
+        let (address, length) = address_length(rest)?;
+        let end = address.wrapping_add(length.max(1) - 1);
+        let (read, write) = match kind {
+            "0" | "1" => {
+                if set {
+                    self.debugger.add_breakpoint(address);
+                } else {
+                    self.debugger.remove_breakpoint(address);
+                }
+                return Some(String::from("OK"));
+            },
+            "2" => (false, true),
+            "3" => (true, false),
+            "4" => (true, true),
+            _ => return Some(String::new())
+        };
+        let watchpoint = Watchpoint { start: address, end, read, write };
+        if set {
+            cpu.memory_mut().add_watchpoint(watchpoint);
+        } else {
+            cpu.memory_mut().remove_exact_watchpoint(watchpoint);
+        }
+        return Some(String::from("OK"));
+    }
+
+    // runs one instruction or until something stops the cpu, returns the stop reply
+    fn resume(&mut self, cpu: &mut Z80, step: bool) -> Result<String> {
+        loop {
+            for _ in 0..INTERRUPT_CHECK {
+                if let Stop::Watchpoint { address, access, .. } = self.debugger.step(cpu) {
+                    return Ok(self.watch_reply(cpu, address, access));
+                }
+                if step || self.debugger.breakpoints().contains(&cpu.pc) {
+                    return Ok(format!("S{:02x}", SIGTRAP));
+                }
+            }
+            if self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    fn watch_reply(&self, cpu: &Z80, address: u16, access: Access) -> String {
+        let both = cpu.memory().watchpoints().iter()
+            .any(|w| w.read && w.write && address >= w.start && address <= w.end);
+        let kind = match access {
+            _ if both => "awatch",
+            Access::Write => "watch",
+            _ => "rwatch"
+        };
+        return format!("T{:02x}{}:{:04x};", SIGTRAP, kind, address);
+    }
+}
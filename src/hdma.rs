@@ -0,0 +1,135 @@
+/*
+cgb vram dma, copies 16 byte blocks from rom or ram into vram:
+
+    0xff51-0xff52  HDMA1/HDMA2, source address, the low 4 bits are ignored
+    0xff53-0xff54  HDMA3/HDMA4, destination in vram, only bits 4-12 count
+    0xff55         HDMA5, bits 0-6 are the number of blocks minus one and
+                   bit 7 picks the mode
+
+general purpose dma (bit 7 = 0) copies everything at once and the cpu is
+stopped until it is done. hblank dma (bit 7 = 1) copies one block at the
+start of every hblank, writing bit 7 = 0 while it runs cancels it
+
+reading HDMA5 gives the blocks left minus one, with bit 7 clear while an
+hblank transfer is running and set once it is finished or cancelled, so
+a completed transfer reads 0xff. each block stops the cpu for 8 machine
+cycles in normal speed and 16 in double speed
+*/
+use std::io::Result;
+
+use crate::savestate::{Savable, StateReader, StateWriter};
+
+pub const BLOCK_SIZE: u16 = 0x10;
+
+pub struct Hdma {
+    source: u16,
+    destination: u16,
+    remaining: u8,
+    hblank_active: bool,
+    general_pending: bool,
+    stall: u16
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        return Hdma {
+            source: 0,
+            destination: 0,
+            remaining: 0,
+            hblank_active: false,
+            general_pending: false,
+            stall: 0
+        };
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        return self.hblank_active;
+    }
+
+    // true once after HDMA5 starts a general purpose transfer
+    pub fn take_general(&mut self) -> bool {
+        let pending = self.general_pending;
+        self.general_pending = false;
+        return pending;
+    }
+
+    // t cycles the cpu has to wait for the blocks copied since the last call
+    pub fn take_stall(&mut self) -> u16 {
+        let stall = self.stall;
+        self.stall = 0;
+        return stall;
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        return match address {
+            0xff55 => ((!self.hblank_active as u8) << 7) | (self.remaining.wrapping_sub(1) & 0x7f),
+            _ => 0xff
+        };
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xff51 => self.source = (self.source & 0x00f0) | ((value as u16) << 8),
+            0xff52 => self.source = (self.source & 0xff00) | (value as u16 & 0xf0),
+            0xff53 => self.destination = (self.destination & 0x00f0) | ((value as u16 & 0x1f) << 8),
+            0xff54 => self.destination = (self.destination & 0x1f00) | (value as u16 & 0xf0),
+            0xff55 => {
+                if self.hblank_active && value & 0x80 == 0 {
+                    self.hblank_active = false;
+                    return;
+                }
+                self.remaining = (value & 0x7f) + 1;
+                self.hblank_active = value & 0x80 != 0;
+                self.general_pending = !self.hblank_active;
+            },
+            _ => {}
+        }
+    }
+
+    // hands out the (source, vram address) of the next block and charges its stall
+    pub fn next_block(&mut self, double_speed: bool) -> Option<(u16, u16)> {
+        if self.remaining == 0 { return None; }
+        let block = (self.source, 0x8000 | self.destination);
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.destination = (self.destination + BLOCK_SIZE) & 0x1ff0;
+        self.remaining -= 1;
+        if self.remaining == 0 { self.hblank_active = false; }
+        self.stall += if double_speed { 64 } else { 32 };
+        return Some(block);
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Hdma {
+        return Hdma::new();
+    }
+}
+
+impl Savable for Hdma {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.source);
+        writer.write_u16(self.destination);
+        writer.write_u8(self.remaining);
+        writer.write_bool(self.hblank_active);
+        writer.write_bool(self.general_pending);
+        writer.write_u16(self.stall);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let source = reader.read_u16()?;
+        let destination = reader.read_u16()?;
+        let remaining = reader.read_u8()?;
+        let hblank_active = reader.read_bool()?;
+        let general_pending = reader.read_bool()?;
+        let stall = reader.read_u16()?;
+        *self = Hdma::new();
+        self.source = source & 0xfff0;
+        self.destination = destination & 0x1ff0;
+        self.remaining = remaining.min(0x80);
+        self.hblank_active = hblank_active && remaining != 0;
+        self.general_pending = general_pending && remaining != 0;
+        self.stall = stall;
+        return Ok(());
+    }
+}
+
@@ -0,0 +1,112 @@
+/*
+callbacks on bus traffic, for tools built on the core: coverage,
+logging, watchpoints of their own. a hook covers an address range and
+one kind of access:
+
+    read      any byte the cpu reads, operands and data alike
+    write     any byte the cpu writes, with the value written
+    execute   the opcode byte of every instruction the cpu runs
+    dma       a byte cgb vram dma copies, at its source address
+
+the event a hook gets carries the bank mapped at the address (see
+MMU::bank_at) so the same address in different banks can be told apart,
+and whether a boot rom was still mapped, to tell its accesses from the
+game's.
+besides dma, hooks only see the cpu's accesses, not peeks from debuggers
+
+MMU keeps a mask of the kinds of access with a hook installed so that
+without any hooks an access costs a single test of it. callbacks can't
+reach the MMU, tools share state with them through an Rc<RefCell<..>>
+*/
+use crate::mmu::Access;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Event {
+    pub access: Access,
+    pub address: u16,
+    pub bank: u16,
+    pub value: u8,
+    // the boot rom has not been unmapped yet
+    pub booting: bool
+}
+
+pub type Callback = Box<dyn FnMut(&Event)>;
+
+// names an installed hook so it can be removed
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HookId(usize);
+
+struct Hook {
+    id: HookId,
+    access: Access,
+    start: u16,
+    end: u16,
+    callback: Callback
+}
+
+// the bit of an access in Hooks::mask
+pub fn bit(access: Access) -> u8 {
+    return match access {
+        Access::Read => 0x01,
+        Access::Write => 0x02,
+        Access::Execute => 0x04,
+        Access::Dma => 0x08
+    };
+}
+
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    next_id: usize
+}
+
+impl Hooks {
+    pub fn new() -> Hooks {
+        return Hooks { hooks: Vec::new(), next_id: 0 };
+    }
+
+    // calls `callback` on every `access` to start..=end
+    pub fn add(&mut self, access: Access, start: u16, end: u16, callback: Callback) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook { id, access, start, end, callback });
+        return id;
+    }
+
+    // false when there is no such hook
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let before = self.hooks.len();
+        self.hooks.retain(|h| h.id != id);
+        return self.hooks.len() != before;
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.hooks.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.hooks.is_empty();
+    }
+
+    // the kinds of access some hook wants, see bit
+    pub fn mask(&self) -> u8 {
+        return self.hooks.iter().fold(0, |mask, h| mask | bit(h.access));
+    }
+
+    pub fn fire(&mut self, event: &Event) {
+        for hook in self.hooks.iter_mut() {
+            if hook.access == event.access && event.address >= hook.start && event.address <= hook.end {
+                (hook.callback)(event);
+            }
+        }
+    }
+}
+
+impl Default for Hooks {
+    fn default() -> Hooks {
+        return Hooks::new();
+    }
+}
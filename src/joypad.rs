@@ -0,0 +1,83 @@
+/*
+P1 (0xff00) reads the eight buttons as two groups of four, picked by
+writing 0 to bit 4 (the directions) or bit 5 (the buttons). a pressed
+button reads as 0 in the low nibble:
+
+    bit    0      1     2       3
+    P14    right  left  up      down
+    P15    a      b     select  start
+
+pressing a button in a selected group requests the joypad interrupt
+*/
+use std::io::Result;
+
+use crate::savestate::{Savable, StateReader, StateWriter};
+
+// bits of the button mask, the directions in the low nibble like P1 reads them
+pub const RIGHT: u8 = 0x01;
+pub const LEFT: u8 = 0x02;
+pub const UP: u8 = 0x04;
+pub const DOWN: u8 = 0x08;
+pub const A: u8 = 0x10;
+pub const B: u8 = 0x20;
+pub const SELECT: u8 = 0x40;
+pub const START: u8 = 0x80;
+
+pub struct Joypad {
+    buttons: u8,
+    select: u8
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        return Joypad { buttons: 0, select: 0x30 };
+    }
+
+    pub fn buttons(&self) -> u8 {
+        return self.buttons;
+    }
+
+    // the low nibble of P1 for the groups selected by `select` (bits 4-5, 0 = selected)
+    fn pressed(&self, select: u8) -> u8 {
+        let mut pressed = 0;
+        if select & 0x10 == 0 { pressed |= self.buttons & 0x0f; }
+        if select & 0x20 == 0 { pressed |= self.buttons >> 4; }
+        return pressed;
+    }
+
+    // sets the held buttons, returns true when the joypad interrupt should fire
+    pub fn set_buttons(&mut self, buttons: u8) -> bool {
+        let before = self.pressed(self.select);
+        self.buttons = buttons;
+        return self.pressed(self.select) & !before != 0;
+    }
+
+    pub fn read(&self) -> u8 {
+        return 0xc0 | self.select | (!self.pressed(self.select) & 0x0f);
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.select = value & 0x30;
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        return Joypad::new();
+    }
+}
+
+impl Savable for Joypad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.buttons);
+        writer.write_u8(self.select);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let buttons = reader.read_u8()?;
+        let select = reader.read_u8()?;
+        self.buttons = buttons;
+        self.select = select & 0x30;
+        return Ok(());
+    }
+}
@@ -0,0 +1,126 @@
+/*
+lcd timing, the part of the ppu that games and the boot rom poll. with
+the lcd on (LCDC bit 7) each of the 154 lines of a frame takes 456 dots,
+a dot being a t cycle at normal speed and two in double speed:
+
+    lines 0-143    mode 2 (oam search) for 80 dots, then mode 3 (drawing)
+                   for 172 and mode 0 (hblank) for the rest of the line
+    lines 144-153  mode 1 (vblank), the vblank interrupt is requested as
+                   line 144 starts
+
+the MMU shows the line in LY (0xff44) and the mode and LY == LYC
+(0xff45) in bits 0-1 and 2 of STAT (0xff41). with the lcd off LY stays 0
+in mode 0. nothing is drawn and the stat interrupt is not raised yet
+*/
+use std::io::Result;
+
+use crate::savestate::{invalid, Savable, StateReader, StateWriter};
+
+pub const DOTS_PER_LINE: u32 = 456;
+pub const LINES: u32 = 154;
+pub const VISIBLE_LINES: u32 = 144;
+
+const MODE_3_START: u32 = 80;
+const MODE_0_START: u32 = 252;
+
+pub const MODE_HBLANK: u8 = 0;
+pub const MODE_VBLANK: u8 = 1;
+pub const MODE_OAM: u8 = 2;
+pub const MODE_DRAWING: u8 = 3;
+
+// what a tick went through
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Passed {
+    pub hblanks: u32,
+    pub vblank: bool
+}
+
+pub struct Lcd {
+    // dots since line 0 started
+    dot: u32,
+    // a cpu cycle left over in double speed, half a dot
+    half_dot: bool
+}
+
+impl Lcd {
+    pub fn new() -> Lcd {
+        return Lcd { dot: 0, half_dot: false };
+    }
+
+    // back to the start of line 0, where switching the lcd on starts
+    pub fn reset(&mut self) {
+        self.dot = 0;
+        self.half_dot = false;
+    }
+
+    // jumps to the start of `line`
+    pub fn set_line(&mut self, line: u8) {
+        self.dot = (line as u32 % LINES) * DOTS_PER_LINE;
+        self.half_dot = false;
+    }
+
+    pub fn line(&self) -> u8 {
+        return (self.dot / DOTS_PER_LINE) as u8;
+    }
+
+    pub fn mode(&self) -> u8 {
+        let x = self.dot % DOTS_PER_LINE;
+        if self.dot / DOTS_PER_LINE >= VISIBLE_LINES { return MODE_VBLANK; }
+        if x < MODE_3_START { return MODE_OAM; }
+        if x < MODE_0_START { return MODE_DRAWING; }
+        return MODE_HBLANK;
+    }
+
+    // runs the lcd for `cycles` cpu cycles
+    pub fn tick(&mut self, cycles: u32, double_speed: bool) -> Passed {
+        let mut dots = cycles;
+        if double_speed {
+            dots = cycles + self.half_dot as u32;
+            self.half_dot = dots & 1 != 0;
+            dots /= 2;
+        }
+
+        let mut passed = Passed::default();
+        while dots > 0 {
+            // stop at every mode change on the way
+            let x = self.dot % DOTS_PER_LINE;
+            let next = if x < MODE_3_START { MODE_3_START } else if x < MODE_0_START { MODE_0_START } else { DOTS_PER_LINE };
+            let step = (next - x).min(dots);
+            dots -= step;
+
+            let before = self.mode();
+            self.dot = (self.dot + step) % (DOTS_PER_LINE * LINES);
+            match self.mode() {
+                mode if mode == before => {},
+                MODE_HBLANK => passed.hblanks += 1,
+                MODE_VBLANK => passed.vblank = true,
+                _ => {}
+            }
+        }
+        return passed;
+    }
+}
+
+impl Default for Lcd {
+    fn default() -> Lcd {
+        return Lcd::new();
+    }
+}
+
+impl Savable for Lcd {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.dot);
+        writer.write_bool(self.half_dot);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let dot = reader.read_u32()?;
+        let half_dot = reader.read_bool()?;
+        if dot >= DOTS_PER_LINE * LINES {
+            return Err(invalid("save state has the lcd past the last line"));
+        }
+        self.dot = dot;
+        self.half_dot = half_dot;
+        return Ok(());
+    }
+}
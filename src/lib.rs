@@ -0,0 +1,32 @@
+pub mod mmu;
+pub mod cpu_macros;
+pub mod cpu;
+pub mod savestate;
+pub mod rewind;
+pub mod rom_loader;
+pub mod mbc;
+pub mod debugger;
+pub mod disasm;
+pub mod trace;
+pub mod serial;
+pub mod cgb;
+pub mod hdma;
+pub mod lcd;
+pub mod palettes;
+pub mod sgb;
+pub mod model;
+pub mod testrom;
+pub mod cli;
+pub mod speed;
+pub mod joypad;
+pub mod crc32;
+pub mod movie;
+pub mod cheats;
+pub mod search;
+pub mod patch;
+pub mod archive;
+pub mod hooks;
+pub mod cdl;
+pub mod symbols;
+pub mod gdb;
+pub mod profiler;
@@ -1,27 +1,440 @@
-mod mmu;
-mod cpu_macros;
-mod cpu;
 
-fn main() {
-    println!("Hello, world!");
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::WindowBuilder;
+
+use gb_emulator::cdl::{self, Cdl, Logger};
+use gb_emulator::cli::{self, Command, Options};
+use gb_emulator::cpu::Z80;
+use gb_emulator::movie::Movie;
+use gb_emulator::profiler::Profiler;
+use gb_emulator::rewind::Rewind;
+use gb_emulator::speed::{Speed, Wait};
+use gb_emulator::symbols::{self, Symbols};
+use gb_emulator::testrom::{self, Outcome};
+use gb_emulator::{debugger, disasm, gdb, joypad, model, palettes, rom_loader, savestate, sgb};
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn machine(options: &Options) -> Z80 {
+    return options.machine().unwrap_or_else(|e| fail(format!("could not load {}: {}", options.rom.display(), e)));
+}
+
+fn read_rom(rom: &Path) -> Vec<u8> {
+    return rom_loader::read_rom(rom).unwrap_or_else(|e| fail(format!("could not load {}: {}", rom.display(), e)));
+}
+
+fn patched_rom(options: &Options) -> Vec<u8> {
+    return options.read_rom().unwrap_or_else(|e| fail(format!("could not load {}: {}", options.rom.display(), e)));
+}
+
+fn info(rom: &Path) {
+    let data = read_rom(rom);
+    let header = match rom_loader::Header::parse(&data) {
+        Some(header) => header,
+        None => fail(format!("{} is too small to have a header", rom.display()))
+    };
+    let ok = |good: bool| if good { "ok" } else { "bad" };
+    println!("title            {}", header.title);
+    println!("cgb flag         {:02X}{}", header.cgb_flag,
+        if header.cgb_only() { " (cgb only)" } else if header.supports_cgb() { " (cgb enhanced)" } else { "" });
+    println!("sgb flag         {:02X}{}", header.sgb_flag, if header.supports_sgb() { " (sgb functions)" } else { "" });
+    println!("cartridge type   {:02X}", header.cartridge_type);
+    println!("rom size         {:02X} ({} KB, file is {} KB)", header.rom_size, header.rom_bytes() / 1024, data.len() / 1024);
+    println!("ram size         {:02X}", header.ram_size);
+    println!("old licensee     {:02X}", header.old_licensee);
+    println!("version          {:02X}", header.version);
+    println!("header checksum  {:02X} ({})", header.header_checksum, ok(header.header_checksum_ok()));
+    println!("global checksum  {:04X}", header.global_checksum);
+    println!("model            {}", model::Model::auto(&header).name());
+}
+
+fn disassemble(rom: &Path, start: Option<usize>, end: Option<usize>) {
+    let data = read_rom(rom);
+    let symbols = symbols::find_beside(rom).map(|path| {
+        Symbols::load(&path).unwrap_or_else(|e| fail(format!("could not load {}: {}", path.display(), e)))
+    });
+    for line in disasm::disassemble_rom_with(&data, start.unwrap_or(0), end.unwrap_or(data.len()), symbols.as_ref()) {
+        println!("{}", line);
+    }
+}
+
+fn debug(options: &Options) {
+    let mut processor = machine(options);
+    let logger = start_cdl(options, &patched_rom(options), &mut processor);
+    start_profiler(options, &mut processor);
+    if let Some(port) = options.gdb {
+        let served = gdb::Server::bind(port).and_then(|server| {
+            println!("waiting for gdb on localhost:{}", server.port()?);
+            return server.serve(&mut processor);
+        });
+        if let Err(e) = served { fail(format!("gdb: {}", e)); }
+        finish_trace(options, &mut processor);
+        save_profile(options, &processor);
+        return save_cdl(options, &logger);
+    }
+    let mut debugger = debugger::Debugger::new();
+    debugger.set_symbols(options.symbols().unwrap_or_else(|e| fail(format!("could not load symbols: {}", e))));
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    if let Err(e) = debugger.repl(&mut processor, stdin.lock(), &mut stdout) {
+        fail(e.to_string());
+    }
+    finish_trace(options, &mut processor);
+    save_profile(options, &processor);
+    save_cdl(options, &logger);
+}
 
-    let mut memory_unit = mmu::MMU::new();
+// starts the code/data log --cdl asks for, carrying on from the file when it exists
+fn start_cdl(options: &Options, rom: &[u8], cpu: &mut Z80) -> Option<Logger> {
+    let path = options.cdl.as_ref()?;
+    let cdl = Cdl::load_or_new(path, rom.len()).unwrap_or_else(|e| fail(format!("could not load {}: {}", path.display(), e)));
+    return Some(Logger::attach(cpu.memory_mut(), cdl));
+}
+
+// starts the profiler --profile asks for
+fn start_profiler(options: &Options, cpu: &mut Z80) {
+    if options.profile.is_none() { return; }
+    let mut profiler = Profiler::new();
+    profiler.set_symbols(options.symbols().unwrap_or_else(|e| fail(format!("could not load symbols: {}", e))));
+    cpu.set_profiler(Some(profiler));
+}
 
-    if memory_unit.set_b(5, 1).is_none() {
-        println!("uh oh");
+// flushes the --trace file, reporting a write that failed on the way
+fn finish_trace(options: &Options, cpu: &mut Z80) {
+    if let (Err(e), Some(path)) = (cpu.set_tracer(None), &options.trace) {
+        eprintln!("could not write {}: {}", path.display(), e);
+    }
+}
+
+// writes the folded stacks and prints where the cycles went
+fn save_profile(options: &Options, cpu: &Z80) {
+    let (profiler, path) = match (cpu.profiler(), &options.profile) {
+        (Some(profiler), Some(path)) => (profiler, path),
+        _ => return
+    };
+    let total = profiler.total().max(1) as f64;
+    let percent = |cycles: u64| cycles as f64 * 100.0 / total;
+    println!("bank   cycles");
+    for (bank, cycles) in profiler.banks() {
+        println!("{:02X}     {:>5.1}%  {}", bank, percent(cycles), cycles);
+    }
+    println!("total   self    function");
+    for function in profiler.functions().iter().take(20) {
+        println!("{:>5.1}%  {:>5.1}%  {}", percent(function.total), percent(function.own), function.name);
     }
+    println!("cycles  instruction");
+    for ((bank, pc), cycles) in profiler.pcs().iter().take(10) {
+        println!("{:>5.1}%  {:02X}:{:04X}  {}", percent(*cycles), bank, pc, cycles);
+    }
+    match profiler.save(path) {
+        Ok(()) => println!("{} cycles profiled, folded stacks written to {}", profiler.total(), path.display()),
+        Err(e) => eprintln!("could not save {}: {}", path.display(), e)
+    }
+}
 
-    if let Some(n) = memory_unit.get_b(5) {
-        println!("{}", n);
+fn save_cdl(options: &Options, logger: &Option<Logger>) {
+    if let (Some(logger), Some(path)) = (logger, &options.cdl) {
+        let cdl = logger.cdl();
+        let total = |flag| (0..cdl.banks()).map(|bank| cdl.count(bank, flag)).sum::<usize>();
+        match cdl.save(path) {
+            Ok(()) => println!("{} bytes of code, {} of operands, {} of data and {} of graphics logged to {}",
+                total(cdl::CODE), total(cdl::OPERAND), total(cdl::DATA), total(cdl::GRAPHICS), path.display()),
+            Err(e) => eprintln!("could not save {}: {}", path.display(), e)
+        }
     }
+}
 
-    let mut t = || memory_unit.set_b(5, 3);
-    t();
-    //let t = mmu::MMU::set_b;
-    //t(&mut memory_unit, 5, 3);
+fn test(options: &Options) {
+    let rom = patched_rom(options);
+    let (status, text) = match testrom::run(&rom, options.model, options.timeout) {
+        Outcome::Passed(text) => ("passed", text),
+        Outcome::Failed(text) => ("failed", text),
+        Outcome::TimedOut(text) => ("timed out", text)
+    };
+    println!("{}", text.trim());
+    println!("{} {}", options.rom.display(), status);
+    process::exit(if status == "passed" { 0 } else { 1 });
+}
 
-    let mut processor = cpu::Z80::new(memory_unit);
+// the ppu is not emulated yet so the game screen stays blank, only an sgb border shows
+struct Screen {
+    shades: Vec<u8>,
+    palette: palettes::Palette
+}
+
+impl Screen {
+    fn size(cpu: &Z80) -> (u32, u32) {
+        if cpu.memory().sgb.is_some() { return (sgb::WIDTH as u32, sgb::HEIGHT as u32); }
+        return (palettes::WIDTH as u32, palettes::HEIGHT as u32);
+    }
 
-    println!("{}", processor.test());
-    
+    fn draw(&self, cpu: &mut Z80, frame: &mut [u8]) {
+        match cpu.memory_mut().sgb.as_mut() {
+            Some(sgb) => sgb.render(&self.shades, frame),
+            None => palettes::render_dmg(&self.shades, &self.palette, frame)
+        }
+    }
+}
+
+// where each frame's buttons come from: the keyboard, a movie being played back or both while recording
+struct Input {
+    playing: Option<Movie>,
+    recording: Option<Movie>,
+    frame: usize
+}
+
+impl Input {
+    fn new(options: &Options, rom: &[u8], cpu: &Z80) -> Input {
+        let playing = options.play.as_ref().map(|path| {
+            Movie::load(path).unwrap_or_else(|e| fail(format!("could not load {}: {}", path.display(), e)))
+        });
+        let recording = options.record.as_ref().map(|_| Movie::power_on(rom, cpu.memory().model()));
+        return Input { playing, recording, frame: 0 };
+    }
+
+    // the machine to run, the one the movie starts from when playing one back
+    fn machine(&self, options: &Options, rom: &[u8]) -> Z80 {
+        return match &self.playing {
+            Some(movie) => movie.machine(rom).unwrap_or_else(|e| fail(format!("could not play the movie: {}", e))),
+            None => machine(options)
+        };
+    }
+
+    // whether a movie is being recorded or still playing, when loading a
+    // state or rewinding would break it
+    fn active(&self) -> bool {
+        return self.recording.is_some() || self.playing.as_ref().map(|m| self.frame < m.len()).unwrap_or(false);
+    }
+
+    fn run_frame(&mut self, cpu: &mut Z80, buttons: u8) {
+        let played = match &self.playing {
+            Some(movie) => movie.play_frame(cpu, self.frame),
+            None => false
+        };
+        if !played {
+            match self.recording.as_mut() {
+                Some(movie) => movie.record_frame(cpu, buttons),
+                None => {
+                    cpu.memory_mut().set_buttons(buttons);
+                    cpu.run_frame();
+                }
+            }
+        }
+        self.frame += 1;
+        if self.frame == self.playing.as_ref().map(|m| m.len()).unwrap_or(0) {
+            println!("movie finished after {} frames", self.frame);
+        }
+    }
+
+    fn finish(&self, options: &Options) {
+        if let (Some(movie), Some(path)) = (&self.recording, &options.record) {
+            match movie.save(path) {
+                Ok(()) => println!("recorded {} frames to {}", movie.len(), path.display()),
+                Err(e) => eprintln!("could not save {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+fn run_headless(options: &Options, frames: u32) {
+    let rom = patched_rom(options);
+    let mut cpu = machine(options);
+    let mut input = Input::new(options, &rom, &cpu);
+    if input.playing.is_some() { cpu = input.machine(options, &rom); }
+    let logger = start_cdl(options, &rom, &mut cpu);
+    start_profiler(options, &mut cpu);
+    for _ in 0..frames {
+        input.run_frame(&mut cpu, 0);
+    }
+    input.finish(options);
+    finish_trace(options, &mut cpu);
+    save_profile(options, &cpu);
+    save_cdl(options, &logger);
+}
+
+fn button(key: VirtualKeyCode) -> Option<u8> {
+    return match key {
+        VirtualKeyCode::Right => Some(joypad::RIGHT),
+        VirtualKeyCode::Left => Some(joypad::LEFT),
+        VirtualKeyCode::Up => Some(joypad::UP),
+        VirtualKeyCode::Down => Some(joypad::DOWN),
+        VirtualKeyCode::X => Some(joypad::A),
+        VirtualKeyCode::Z => Some(joypad::B),
+        VirtualKeyCode::RShift | VirtualKeyCode::Back => Some(joypad::SELECT),
+        VirtualKeyCode::Return => Some(joypad::START),
+        _ => None
+    };
+}
+
+const REWIND_SECONDS: u32 = 30;
+const REWIND_INTERVAL: u32 = 4;
+
+/*
+keys while running:
+    arrows      the joypad
+    x and z     a and b
+    enter       start
+    backspace   select (right shift too)
+    escape      quit
+    0-9         pick the save slot
+    f5          save to the slot
+    f8          load from the slot
+    space       pause
+    n           advance one frame, pausing first
+    tab         turbo while held
+    - and =     slower and faster, 0.25x to 8x
+    r           rewind while held, up to 30 seconds
+
+save states and rewinding are off while a movie records or plays
+*/
+fn run(options: Options) {
+    if let Some(frames) = options.headless {
+        return run_headless(&options, frames);
+    }
+
+    let rom = patched_rom(&options);
+    let mut cpu = machine(&options);
+    let mut input = Input::new(&options, &rom, &cpu);
+    if input.playing.is_some() { cpu = input.machine(&options, &rom); }
+    let logger = start_cdl(&options, &rom, &mut cpu);
+    start_profiler(&options, &mut cpu);
+    let mut buttons = 0;
+    let screen = Screen { shades: vec![0; palettes::WIDTH * palettes::HEIGHT], palette: options.palette };
+    let (width, height) = Screen::size(&cpu);
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title(format!("gb_emulator - {}", options.rom.display()))
+        .with_inner_size(LogicalSize::new(width * options.scale, height * options.scale))
+        .build(&event_loop)
+        .unwrap_or_else(|e| fail(format!("could not open a window: {}", e)));
+    let size = window.inner_size();
+    let mut pixels = Pixels::new(width, height, SurfaceTexture::new(size.width, size.height, &window))
+        .unwrap_or_else(|e| fail(format!("could not start rendering: {}", e)));
+
+    let mut speed = Speed::new();
+    speed.set_multiplier(options.speed);
+    let mut slot = 0;
+    let mut rewind = Rewind::new(REWIND_SECONDS, REWIND_INTERVAL);
+    let mut rewinding = false;
+
+    event_loop.run(move |event, _, control_flow| {
+        match event {
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => *control_flow = ControlFlow::Exit,
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => pixels.resize(size.width, size.height),
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input: KeyboardInput { state, virtual_keycode: Some(key), .. }, .. }, ..
+            } => {
+                if key == VirtualKeyCode::Tab {
+                    speed.set_turbo(state == ElementState::Pressed);
+                }
+                if key == VirtualKeyCode::R {
+                    rewinding = state == ElementState::Pressed && !input.active();
+                }
+                if let Some(button) = button(key) {
+                    if state == ElementState::Pressed { buttons |= button; } else { buttons &= !button; }
+                }
+                if state != ElementState::Pressed { return; }
+
+                let save_dir = options.save_dir.as_deref();
+                let result = match key {
+                    VirtualKeyCode::Escape => {
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    },
+                    VirtualKeyCode::F5 | VirtualKeyCode::F8 if input.active() => {
+                        eprintln!("save states are off while a movie is recording or playing");
+                        return;
+                    },
+                    VirtualKeyCode::F5 => savestate::save_slot(&cpu, &options.rom, save_dir, slot),
+                    VirtualKeyCode::F8 => savestate::load_slot(&mut cpu, &options.rom, save_dir, slot),
+                    _ => {
+                        match key {
+                            VirtualKeyCode::Space => speed.toggle_pause(),
+                            VirtualKeyCode::N => speed.advance_frame(),
+                            VirtualKeyCode::Minus => speed.slower(),
+                            VirtualKeyCode::Equals => speed.faster(),
+                            _ => if let Some(n) = slot_key(key) { slot = n; }
+                        }
+                        return;
+                    }
+                };
+                match result {
+                    Ok(path) => println!("slot {}: {}", slot, path.display()),
+                    Err(e) => eprintln!("slot {}: {}", slot, e)
+                }
+            },
+            Event::MainEventsCleared => {
+                let frames = speed.frames_due(Instant::now());
+                for _ in 0..frames {
+                    if rewinding {
+                        // one snapshot back per frame, so rewinding is faster than playing
+                        if let Err(e) = rewind.step_back(&mut cpu) {
+                            eprintln!("could not rewind: {}", e);
+                        }
+                    } else {
+                        input.run_frame(&mut cpu, buttons);
+                        rewind.record_frame(&cpu);
+                    }
+                }
+                if frames > 0 { window.request_redraw(); }
+                *control_flow = match speed.wait() {
+                    Wait::Now => ControlFlow::Poll,
+                    Wait::Until(next) => ControlFlow::WaitUntil(next),
+                    Wait::Input => ControlFlow::Wait
+                };
+            },
+            Event::LoopDestroyed => {
+                input.finish(&options);
+                finish_trace(&options, &mut cpu);
+                save_profile(&options, &cpu);
+                save_cdl(&options, &logger);
+            },
+            Event::RedrawRequested(_) => {
+                screen.draw(&mut cpu, pixels.get_frame());
+                if let Err(e) = pixels.render() {
+                    eprintln!("could not draw: {}", e);
+                    *control_flow = ControlFlow::Exit;
+                }
+            },
+            _ => {}
+        }
+    });
+}
+
+fn slot_key(key: VirtualKeyCode) -> Option<u8> {
+    let keys = [
+        VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4,
+        VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9
+    ];
+    return keys.iter().position(|k| *k == key).map(|n| n as u8);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(e) => fail(format!("{}\n\n{}", e, cli::USAGE))
+    };
+
+    match command {
+        Command::Run(options) => run(options),
+        Command::Info(rom) => info(&rom),
+        Command::Disasm { rom, start, end } => disassemble(&rom, start, end),
+        Command::Debug(options) => debug(&options),
+        Command::Test(options) => test(&options),
+        Command::Help => print!("{}", cli::USAGE)
+    }
 }
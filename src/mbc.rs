@@ -0,0 +1,233 @@
+/*
+cartridge memory bank controllers. the whole rom image is kept here and
+the mbc picks which 16KB bank shows at 0x4000-0x7fff (and, for mbc1 in
+mode 1, at 0x0000-0x3fff) and which 8KB bank of cartridge ram shows at
+0xa000-0xbfff. the cpu's writes to the rom area never change the rom,
+they set the registers:
+
+    0x0000-0x1fff  ram enable, 0x0a in the low nibble enables
+    0x2000-0x3fff  rom bank, 5 bits on mbc1 (0 reads as 1), 7 on mbc3,
+                   8 on mbc5 plus bit 8 at 0x3000-0x3fff
+    0x4000-0x5fff  ram bank, or the upper 2 rom bank bits on mbc1
+    0x6000-0x7fff  mbc1 banking mode
+
+mbc2 has 4 bit rom banks and 512 half bytes of built in ram, with bit 8
+of the address telling its two registers apart. the mbc3 clock is not
+emulated, selecting its registers reads 0xff. cartridges with another
+mapper run as if they had none
+*/
+use std::io::Result;
+
+use crate::rom_loader::Header;
+use crate::savestate::{Savable, StateReader, StateWriter};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5
+}
+
+impl Kind {
+    // the mbc of cartridge type `cartridge_type` (0x0147 in the header)
+    pub fn from_type(cartridge_type: u8) -> Kind {
+        return match cartridge_type {
+            0x01..=0x03 => Kind::Mbc1,
+            0x05 | 0x06 => Kind::Mbc2,
+            0x0f..=0x13 => Kind::Mbc3,
+            0x19..=0x1e => Kind::Mbc5,
+            _ => Kind::None
+        };
+    }
+}
+
+// bytes of cartridge ram for ram size code `code` (0x0149 in the header)
+fn ram_bytes(kind: Kind, code: u8) -> usize {
+    if kind == Kind::Mbc2 { return 512; }
+    return match code {
+        1 => 0x800,
+        2 => 0x2000,
+        3 => 0x8000,
+        4 => 0x20000,
+        5 => 0x10000,
+        _ => 0
+    };
+}
+
+pub struct Mbc {
+    kind: Kind,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // the registers as written, masked when used
+    rom_bank: u16,
+    ram_bank: u8,
+    mode: bool
+}
+
+impl Mbc {
+    // an empty slot, every read is 0xff
+    pub fn new() -> Mbc {
+        return Mbc { kind: Kind::None, rom: Vec::new(), ram: Vec::new(), ram_enabled: false, rom_bank: 1, ram_bank: 0, mode: false };
+    }
+
+    // the mbc and ram the header of `rom` asks for
+    pub fn from_rom(rom: &[u8]) -> Mbc {
+        let (kind, ram_size) = match Header::parse(rom) {
+            Some(header) => (Kind::from_type(header.cartridge_type), header.ram_size),
+            None => (Kind::None, 0)
+        };
+        let mut mbc = Mbc::new();
+        mbc.kind = kind;
+        mbc.rom = rom.to_vec();
+        mbc.ram = vec![0; ram_bytes(kind, ram_size)];
+        // without an mbc there is nothing to enable the ram with
+        mbc.ram_enabled = kind == Kind::None;
+        return mbc;
+    }
+
+    // the registers as they are at power on, the ram keeps its contents
+    pub fn reset(&mut self) {
+        self.ram_enabled = self.kind == Kind::None;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.mode = false;
+    }
+
+    pub fn kind(&self) -> Kind {
+        return self.kind;
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        return &self.rom;
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        return &self.ram;
+    }
+
+    fn rom_banks(&self) -> usize {
+        return (self.rom.len() / ROM_BANK_SIZE).max(1);
+    }
+
+    // the rom bank at 0x0000-0x3fff, only ever not 0 on mbc1 in mode 1
+    pub fn low_rom_bank(&self) -> u16 {
+        let bank = match self.kind {
+            Kind::Mbc1 if self.mode => ((self.ram_bank & 0x03) as usize) << 5,
+            _ => 0
+        };
+        return (bank % self.rom_banks()) as u16;
+    }
+
+    // the rom bank at 0x4000-0x7fff
+    pub fn rom_bank(&self) -> u16 {
+        let nonzero = |bank: u16| if bank == 0 { 1 } else { bank };
+        let bank = match self.kind {
+            Kind::None => 1,
+            Kind::Mbc1 => (((self.ram_bank & 0x03) as u16) << 5) | nonzero(self.rom_bank & 0x1f),
+            Kind::Mbc2 => nonzero(self.rom_bank & 0x0f),
+            Kind::Mbc3 => nonzero(self.rom_bank & 0x7f),
+            Kind::Mbc5 => self.rom_bank & 0x1ff
+        };
+        return (bank as usize % self.rom_banks()) as u16;
+    }
+
+    // the ram bank at 0xa000-0xbfff, None when an mbc3 clock register is selected instead
+    pub fn ram_bank(&self) -> Option<u16> {
+        return match self.kind {
+            Kind::Mbc1 if self.mode => Some((self.ram_bank & 0x03) as u16),
+            Kind::Mbc3 if self.ram_bank > 0x03 => None,
+            Kind::Mbc3 => Some(self.ram_bank as u16),
+            Kind::Mbc5 => Some((self.ram_bank & 0x0f) as u16),
+            _ => Some(0)
+        };
+    }
+
+    // where 0xa000 + `offset` is in the ram, None when nothing answers there
+    fn ram_index(&self, offset: usize) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() { return None; }
+        if self.kind == Kind::Mbc2 { return Some(offset & 0x1ff); }
+        let bank = self.ram_bank()? as usize;
+        return Some((bank * RAM_BANK_SIZE + offset) % self.ram.len());
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        let rom_index = |bank: u16, offset: usize| bank as usize * ROM_BANK_SIZE + offset;
+        let index = match address {
+            0x0000..=0x3fff => rom_index(self.low_rom_bank(), address as usize),
+            0x4000..=0x7fff => rom_index(self.rom_bank(), address as usize - 0x4000),
+            0xa000..=0xbfff => {
+                return match self.ram_index(address as usize - 0xa000) {
+                    // only the low nibble of mbc2 ram exists
+                    Some(i) if self.kind == Kind::Mbc2 => self.ram[i] | 0xf0,
+                    Some(i) => self.ram[i],
+                    None => 0xff
+                };
+            },
+            _ => return 0xff
+        };
+        return self.rom.get(index).copied().unwrap_or(0xff);
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match (self.kind, address) {
+            (_, 0xa000..=0xbfff) => {
+                if let Some(i) = self.ram_index(address as usize - 0xa000) { self.ram[i] = value; }
+            },
+            (Kind::None, _) => {},
+            (Kind::Mbc2, 0x0000..=0x3fff) => {
+                if address & 0x100 == 0 {
+                    self.ram_enabled = value & 0x0f == 0x0a;
+                } else {
+                    self.rom_bank = value as u16;
+                }
+            },
+            (_, 0x0000..=0x1fff) => self.ram_enabled = value & 0x0f == 0x0a,
+            (Kind::Mbc5, 0x2000..=0x2fff) => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            (Kind::Mbc5, 0x3000..=0x3fff) => self.rom_bank = (self.rom_bank & 0xff) | (((value & 0x01) as u16) << 8),
+            (_, 0x2000..=0x3fff) => self.rom_bank = value as u16,
+            (Kind::Mbc2, _) => {},
+            (_, 0x4000..=0x5fff) => self.ram_bank = value,
+            (Kind::Mbc1, 0x6000..=0x7fff) => self.mode = value & 0x01 != 0,
+            _ => {}
+        }
+    }
+}
+
+impl Default for Mbc {
+    fn default() -> Mbc {
+        return Mbc::new();
+    }
+}
+
+
+// the rom is the cartridge's, only the registers and ram are machine state
+impl Savable for Mbc {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.ram_enabled);
+        writer.write_u16(self.rom_bank);
+        writer.write_u8(self.ram_bank);
+        writer.write_bool(self.mode);
+        writer.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let ram_enabled = reader.read_bool()?;
+        let rom_bank = reader.read_u16()?;
+        let ram_bank = reader.read_u8()?;
+        let mode = reader.read_bool()?;
+        let mut ram = vec![0; self.ram.len()];
+        reader.read_into(&mut ram)?;
+        self.ram_enabled = ram_enabled;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.mode = mode;
+        self.ram = ram;
+        return Ok(());
+    }
+}
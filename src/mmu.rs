@@ -50,20 +50,280 @@ macro_rules! mem_access_w {
     }
 }
 
+use crate::cgb::Cgb;
+use crate::cheats::Cheats;
+use crate::hooks::{self, Callback, Event, HookId, Hooks};
+use crate::hdma::BLOCK_SIZE;
+use crate::joypad::Joypad;
+use crate::mbc::Mbc;
+use crate::model::Model;
+use crate::savestate::{invalid, Savable, StateReader, StateWriter};
+use crate::serial::Serial;
+use crate::lcd::{Lcd, MODE_HBLANK};
+use crate::sgb::{self, Sgb, Transfer};
+use std::cell::{Cell, RefCell};
+use std::io::Result;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    // an opcode fetch
+    Execute,
+    // a byte read by vram dma
+    Dma
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WatchHit {
+    pub address: u16,
+    pub access: Access,
+    pub value: u8
+}
+
+pub const IF_ADDRESS: u16 = 0xff0f;
+pub const IE_ADDRESS: u16 = 0xffff;
+pub const LCDC_ADDRESS: u16 = 0xff40;
+pub const STAT_ADDRESS: u16 = 0xff41;
+pub const LY_ADDRESS: u16 = 0xff44;
+pub const LYC_ADDRESS: u16 = 0xff45;
+
+pub const VBLANK_INTERRUPT: u8 = 0x01;
+pub const STAT_INTERRUPT: u8 = 0x02;
+pub const TIMER_INTERRUPT: u8 = 0x04;
+pub const SERIAL_INTERRUPT: u8 = 0x08;
+pub const JOYPAD_INTERRUPT: u8 = 0x10;
+
 pub struct MMU {
-    mem: Vec<u8>
+    mem: Vec<u8>,
+    pub mbc: Mbc,
+    pub serial: Serial,
+    pub lcd: Lcd,
+    pub joypad: Joypad,
+    pub cheats: Cheats,
+    pub cgb: Option<Cgb>,
+    pub sgb: Option<Sgb>,
+    model: Model,
+    boot_rom: Option<Vec<u8>>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Cell<Option<WatchHit>>,
+    hooks: RefCell<Hooks>,
+    // hooks::bit of every kind of access a hook is installed for
+    hooked: u8
 }
 
 impl MMU {
     pub fn new() -> MMU {
         return MMU {
-            mem: vec![0; 0xffff]
+            mem: vec![0; 0x10000],
+            mbc: Mbc::new(),
+            serial: Serial::new(),
+            lcd: Lcd::new(),
+            joypad: Joypad::new(),
+            cheats: Cheats::new(),
+            cgb: None,
+            sgb: None,
+            model: Model::Dmg,
+            boot_rom: None,
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            hooks: RefCell::new(Hooks::new()),
+            hooked: 0
+        };
+    }
+
+    // inserts the cartridge, with the mbc its header asks for
+    pub fn load_rom(&mut self, rom: &[u8]) {
+        self.mbc = Mbc::from_rom(rom);
+    }
+
+    /*
+    maps a boot rom over the cartridge until the game writes to 0xff50.
+    the 256 byte dmg rom covers 0x0000-0x00ff, the 2304 byte cgb one also
+    covers 0x0200-0x08ff and leaves the cartridge header visible
+    */
+    pub fn set_boot_rom(&mut self, rom: Vec<u8>) {
+        self.boot_rom = Some(rom);
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        return self.boot_rom.is_some();
+    }
+
+    // the boot rom while it is mapped
+    pub fn boot_rom(&self) -> Option<&[u8]> {
+        return self.boot_rom.as_deref();
+    }
+
+    pub fn unmap_boot_rom(&mut self) {
+        self.boot_rom = None;
+    }
+
+    pub fn model(&self) -> Model {
+        return self.model;
+    }
+
+    // picks the hardware on the bus, `cgb_mode` is false for cgb models running dmg cartridges
+    pub fn set_model(&mut self, model: Model, cgb_mode: bool) {
+        self.model = model;
+        self.set_cgb(model.is_cgb() && cgb_mode);
+        self.set_sgb(model.is_sgb());
+    }
+
+    // switches the cgb banks and registers in or out
+    pub fn set_cgb(&mut self, enabled: bool) {
+        if enabled != self.cgb.is_some() {
+            self.cgb = if enabled { Some(Cgb::new()) } else { None };
+        }
+        self.serial.set_cgb(enabled);
+    }
+
+    // switches super game boy packet decoding on or off
+    pub fn set_sgb(&mut self, enabled: bool) {
+        if enabled != self.sgb.is_some() {
+            self.sgb = if enabled { Some(Sgb::new()) } else { None };
+        }
+    }
+
+    /*
+    a CHR_TRN or PCT_TRN copies the 4KB of tile data the background uses.
+    games lay their tiles out in order on screen, with no ppu frame to wait
+    for we take them straight from vram
+    */
+    fn sgb_transfer(&mut self, transfer: Transfer) {
+        let start: u16 = if self.read(0xff40) & 0x10 != 0 { 0x8000 } else { 0x8800 };
+        let data: Vec<u8> = (0..sgb::TRANSFER_SIZE as u16).map(|i| self.read(start + i)).collect();
+        if let Some(sgb) = self.sgb.as_mut() {
+            sgb.complete_transfer(transfer, &data);
+        }
+    }
+
+    // holds down the buttons in `buttons` (joypad::A, joypad::START...) and releases the rest
+    pub fn set_buttons(&mut self, buttons: u8) {
+        if self.joypad.set_buttons(buttons) {
+            self.request_interrupt(JOYPAD_INTERRUPT);
+        }
+    }
+
+    fn write_p1(&mut self, value: u8) {
+        self.joypad.write(value);
+        let transfer = match self.sgb.as_mut() {
+            Some(sgb) => {
+                sgb.write_p1(value);
+                sgb.take_transfer()
+            },
+            None => None
+        };
+        if let Some(transfer) = transfer { self.sgb_transfer(transfer); }
+    }
+
+    // makes the writes of the enabled gameshark codes, once a frame
+    pub fn apply_cheats(&mut self) {
+        if self.cheats.is_empty() { return; }
+        for (address, value) in self.cheats.writes() {
+            self.write(address, value);
+        }
+    }
+
+    pub fn double_speed(&self) -> bool {
+        return self.cgb.as_ref().map(|c| c.double_speed()).unwrap_or(false);
+    }
+
+    // called by STOP, returns true if an armed cgb speed switch happened
+    pub fn switch_speed(&mut self) -> bool {
+        return self.cgb.as_mut().map(|c| c.switch_speed()).unwrap_or(false);
+    }
+
+    // copies the next hdma block into vram, false when there was none
+    fn dma_block(&mut self) -> bool {
+        let double_speed = self.double_speed();
+        let block = self.cgb.as_mut().and_then(|cgb| cgb.hdma.next_block(double_speed));
+        let (source, destination) = match block {
+            Some(block) => block,
+            None => return false
         };
+        for i in 0..BLOCK_SIZE {
+            let value = self.read(source.wrapping_add(i));
+            if self.hooked & hooks::bit(Access::Dma) != 0 { self.fire(Access::Dma, source.wrapping_add(i), value); }
+            self.write(destination + i, value);
+        }
+        return true;
+    }
+
+    fn general_dma(&mut self) {
+        while self.dma_block() {}
+    }
+
+    // called at the start of every hblank to run a pending hblank dma
+    pub fn hblank(&mut self) {
+        if self.cgb.as_ref().map(|cgb| cgb.hdma.hblank_active()).unwrap_or(false) {
+            self.dma_block();
+        }
+    }
+
+    // t cycles the cpu is stopped for by vram dma since the last call
+    pub fn take_dma_stall(&mut self) -> u16 {
+        return self.cgb.as_mut().map(|cgb| cgb.hdma.take_stall()).unwrap_or(0);
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        if let Some(boot) = &self.boot_rom {
+            let i = address as usize;
+            if (i < 0x100 || (0x200..0x900).contains(&i)) && i < boot.len() { return boot[i]; }
+        }
+        if address < 0x8000 && self.cheats.patches_rom() {
+            return self.cheats.read_rom(address, self.mbc.read(address));
+        }
+        if let Some(cgb) = &self.cgb {
+            if Cgb::maps(address) { return cgb.read(address); }
+        }
+        return match (address, &self.sgb) {
+            // with both select lines high the sgb answers which joypad is being read
+            (0xff00, Some(sgb)) if self.joypad.read() & 0x30 == 0x30 => (self.joypad.read() & 0xf0) | (0x0f - sgb.player()),
+            (0xff00, _) => self.joypad.read(),
+            (0xff01, _) | (0xff02, _) => self.serial.read(address),
+            (0x0000..=0x7fff, _) | (0xa000..=0xbfff, _) => self.mbc.read(address),
+            _ => self.mem[address as usize]
+        };
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if let Some(cgb) = &mut self.cgb {
+            if Cgb::maps(address) {
+                cgb.write(address, value);
+                if cgb.hdma.take_general() { self.general_dma(); }
+                return;
+            }
+        }
+        match address {
+            // writes to the rom set the mbc registers
+            0x0000..=0x7fff | 0xa000..=0xbfff => self.mbc.write(address, value),
+            0xff00 => self.write_p1(value),
+            0xff50 if value != 0 => {
+                self.boot_rom = None;
+                self.mem[address as usize] = value;
+            },
+            0xff01 | 0xff02 => self.serial.write(address, value),
+            // the mode and coincidence bits of STAT and all of LY are read only
+            STAT_ADDRESS => self.mem[address as usize] = 0x80 | (value & 0x78) | (self.mem[address as usize] & 0x07),
+            LY_ADDRESS => {},
+            _ => self.mem[address as usize] = value
+        }
     }
 
     pub fn set_b(&mut self, address: u16, value: u8) -> Option<u8> {
         if address as usize >= self.mem.len() { return None; }
-        self.mem[address as usize] = value;
+        if !self.watchpoints.is_empty() { self.check_watch(address, Access::Write, value); }
+        if self.hooked & hooks::bit(Access::Write) != 0 { self.fire(Access::Write, address, value); }
+        self.write(address, value);
         return Some(value);
     }
 
@@ -71,20 +331,208 @@ impl MMU {
         if address as usize >= self.mem.len() - 1 { return None; }
         let first_byte = (value & 0xff) as u8;
         let second_byte = (value >> 8) as u8;
-        self.mem[address as usize] = first_byte;
-        self.mem[(address + 1) as usize] = second_byte;
+        if !self.watchpoints.is_empty() {
+            self.check_watch(address, Access::Write, first_byte);
+            self.check_watch(address + 1, Access::Write, second_byte);
+        }
+        if self.hooked & hooks::bit(Access::Write) != 0 {
+            self.fire(Access::Write, address, first_byte);
+            self.fire(Access::Write, address + 1, second_byte);
+        }
+        self.write(address, first_byte);
+        self.write(address + 1, second_byte);
         return Some(value);
     }
 
     pub fn get_b(&self, address: u16) -> Option<u8> {
         if address as usize >= self.mem.len() { return None; }
-        return Some(self.mem[address as usize]);
+        let value = self.read(address);
+        if !self.watchpoints.is_empty() { self.check_watch(address, Access::Read, value); }
+        if self.hooked & hooks::bit(Access::Read) != 0 { self.fire(Access::Read, address, value); }
+        return Some(value);
     }
 
     pub fn get_w(&self, address: u16) -> Option<u16> {
         if address as usize >= self.mem.len() - 1 { return None; }
-        let mut value: u16 = self.mem[address as usize] as u16;
-        value |= (self.mem[(address + 1) as usize] as u16) << 8;
+        let mut value: u16 = self.read(address) as u16;
+        value |= (self.read(address + 1) as u16) << 8;
+        if !self.watchpoints.is_empty() {
+            self.check_watch(address, Access::Read, (value & 0xff) as u8);
+            self.check_watch(address + 1, Access::Read, (value >> 8) as u8);
+        }
+        if self.hooked & hooks::bit(Access::Read) != 0 {
+            self.fire(Access::Read, address, (value & 0xff) as u8);
+            self.fire(Access::Read, address + 1, (value >> 8) as u8);
+        }
+        return Some(value);
+    }
+
+    // reads the opcode of the instruction at `address`, which execute hooks see instead of read hooks
+    pub fn fetch(&self, address: u16) -> Option<u8> {
+        if address as usize >= self.mem.len() { return None; }
+        let value = self.read(address);
+        if !self.watchpoints.is_empty() { self.check_watch(address, Access::Execute, value); }
+        if self.hooked & hooks::bit(Access::Execute) != 0 { self.fire(Access::Execute, address, value); }
         return Some(value);
     }
-}
\ No newline at end of file
+
+    // advances the peripherals by the cycles the last instruction took
+    pub fn tick(&mut self, cycles: u32) {
+        if self.serial.tick(cycles) {
+            self.request_interrupt(SERIAL_INTERRUPT);
+        }
+        self.tick_lcd(cycles);
+    }
+
+    // runs the lcd timing, which starts hblank dma and the vblank interrupt
+    fn tick_lcd(&mut self, cycles: u32) {
+        let on = self.mem[LCDC_ADDRESS as usize] & 0x80 != 0;
+        if on {
+            let passed = self.lcd.tick(cycles, self.double_speed());
+            for _ in 0..passed.hblanks {
+                self.hblank();
+            }
+            if passed.vblank { self.request_interrupt(VBLANK_INTERRUPT); }
+        } else {
+            self.lcd.reset();
+        }
+
+        let (line, mode) = if on { (self.lcd.line(), self.lcd.mode()) } else { (0, MODE_HBLANK) };
+        let coincidence = if line == self.mem[LYC_ADDRESS as usize] { 0x04 } else { 0 };
+        let stat = self.mem[STAT_ADDRESS as usize];
+        self.mem[STAT_ADDRESS as usize] = 0x80 | (stat & 0x78) | coincidence | mode;
+        self.mem[LY_ADDRESS as usize] = line;
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: u8) {
+        self.mem[IF_ADDRESS as usize] |= interrupt;
+    }
+
+    pub fn clear_interrupt(&mut self, interrupt: u8) {
+        self.mem[IF_ADDRESS as usize] &= !interrupt;
+    }
+
+    // interrupts that are both requested and enabled
+    pub fn pending_interrupts(&self) -> u8 {
+        return self.mem[IF_ADDRESS as usize] & self.mem[IE_ADDRESS as usize] & 0x1f;
+    }
+
+    // the bank mapped at `address`, for the switchable regions
+    pub fn bank_at(&self, address: u16) -> u16 {
+        return match (address, &self.cgb) {
+            (0x0000..=0x3fff, _) => self.mbc.low_rom_bank(),
+            (0x4000..=0x7fff, _) => self.mbc.rom_bank(),
+            (0xa000..=0xbfff, _) => self.mbc.ram_bank().unwrap_or(0),
+            (0x8000..=0x9fff, Some(cgb)) => cgb.vram_bank() as u16,
+            (0xd000..=0xdfff, Some(cgb)) => cgb.wram_bank() as u16,
+            (0xd000..=0xdfff, None) => 1,
+            _ => 0
+        };
+    }
+
+    // reads a byte without triggering watchpoints, for debuggers and tools
+    pub fn peek(&self, address: u16) -> u8 {
+        return self.read(address);
+    }
+
+    // writes a byte without triggering watchpoints or hooks. a write to a
+    // register still does what the cpu's would, 0xff55 starts a vram dma and
+    // 0xff50 unmaps the boot rom
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.write(address, value);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    // removes every watchpoint covering `address`, returns how many were removed
+    pub fn remove_watchpoint(&mut self, address: u16) -> usize {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|w| address < w.start || address > w.end);
+        return before - self.watchpoints.len();
+    }
+
+    // removes one watchpoint exactly as it was added, false when there is none
+    pub fn remove_exact_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        match self.watchpoints.iter().position(|w| *w == watchpoint) {
+            Some(i) => { self.watchpoints.remove(i); },
+            None => return false
+        }
+        return true;
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        return &self.watchpoints;
+    }
+
+    // the first watchpoint hit since the last call
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        return self.watch_hit.take();
+    }
+
+    // calls `callback` on every `access` the cpu makes to start..=end, see hooks
+    pub fn add_hook(&mut self, access: Access, start: u16, end: u16, callback: Callback) -> HookId {
+        let id = self.hooks.get_mut().add(access, start, end, callback);
+        self.hooked = self.hooks.get_mut().mask();
+        return id;
+    }
+
+    // false when there is no such hook
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let removed = self.hooks.get_mut().remove(id);
+        self.hooked = self.hooks.get_mut().mask();
+        return removed;
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.get_mut().clear();
+        self.hooked = 0;
+    }
+
+    fn fire(&self, access: Access, address: u16, value: u8) {
+        let event = Event { access, address, bank: self.bank_at(address), value, booting: self.boot_rom.is_some() };
+        self.hooks.borrow_mut().fire(&event);
+    }
+
+    fn check_watch(&self, address: u16, access: Access, value: u8) {
+        if self.watch_hit.get().is_some() { return; }
+        for w in self.watchpoints.iter() {
+            // opcode fetches are not data reads, they only show up to execute hooks
+            let wanted = match access {
+                Access::Read | Access::Dma => w.read,
+                Access::Write => w.write,
+                Access::Execute => false
+            };
+            if wanted && address >= w.start && address <= w.end {
+                self.watch_hit.set(Some(WatchHit { address, access, value }));
+                return;
+            }
+        }
+    }
+}
+
+impl Default for MMU {
+    fn default() -> MMU {
+        return MMU::new();
+    }
+}
+
+impl Savable for MMU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.mem);
+        writer.write_u8(self.model.id());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let mut mem = vec![0; self.mem.len()];
+        reader.read_into(&mut mem)?;
+        let model = match Model::from_id(reader.read_u8()?) {
+            Some(model) => model,
+            None => return Err(invalid("save state has an unknown model"))
+        };
+        self.mem = mem;
+        self.model = model;
+        return Ok(());
+    }
+}
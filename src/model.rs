@@ -0,0 +1,100 @@
+/*
+the hardware revision being emulated. besides which extra hardware the
+bus has (cgb banks and palettes, sgb packets) the models differ in the
+registers their boot roms leave behind, which games read to tell them
+apart: a = 0x11 means a cgb or agb, b bit 0 set on top of that an agb,
+a = 0xff a pocket
+
+a cgb or agb running a cartridge without cgb support falls back to dmg
+compatibility mode, where the cgb hardware is hidden from the game
+*/
+use crate::rom_loader::Header;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Model {
+    // the first dmg boot rom
+    Dmg0,
+    Dmg,
+    // game boy pocket and light
+    Mgb,
+    Sgb,
+    Cgb,
+    // game boy advance running game boy cartridges
+    Agb
+}
+
+pub const MODELS: [Model; 6] = [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb, Model::Agb];
+
+// af, bc, de, hl after the boot rom
+pub struct Registers {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16
+}
+
+impl Model {
+    pub fn parse(name: &str) -> Option<Model> {
+        return MODELS.iter().find(|m| m.name() == name.to_ascii_lowercase()).copied();
+    }
+
+    // the number save states and movies store the model as
+    pub fn id(&self) -> u8 {
+        return MODELS.iter().position(|m| m == self).unwrap() as u8;
+    }
+
+    pub fn from_id(id: u8) -> Option<Model> {
+        return MODELS.get(id as usize).copied();
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Cgb => "cgb",
+            Model::Agb => "agb"
+        };
+    }
+
+    // the model a cartridge was made for: cgb when it supports it, then sgb, then dmg
+    pub fn auto(header: &Header) -> Model {
+        if header.supports_cgb() { return Model::Cgb; }
+        if header.supports_sgb() { return Model::Sgb; }
+        return Model::Dmg;
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        return matches!(self, Model::Cgb | Model::Agb);
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        return *self == Model::Sgb;
+    }
+
+    // whether a cartridge runs with the cgb hardware switched on
+    pub fn cgb_mode(&self, header: Option<&Header>) -> bool {
+        return self.is_cgb() && header.map(|h| h.supports_cgb()).unwrap_or(false);
+    }
+
+    pub fn registers(&self, cgb_mode: bool) -> Registers {
+        let (af, bc, de, hl) = match (self, cgb_mode) {
+            (Model::Dmg0, _) => (0x0100, 0xff13, 0x00c1, 0x8403),
+            (Model::Dmg, _) => (0x01b0, 0x0013, 0x00d8, 0x014d),
+            (Model::Mgb, _) => (0xffb0, 0x0013, 0x00d8, 0x014d),
+            (Model::Sgb, _) => (0x0100, 0x0014, 0x0000, 0xc060),
+            (Model::Cgb, true) => (0x1180, 0x0000, 0xff56, 0x000d),
+            (Model::Cgb, false) => (0x1180, 0x0000, 0x0008, 0x007c),
+            (Model::Agb, true) => (0x1100, 0x0100, 0xff56, 0x000d),
+            (Model::Agb, false) => (0x1100, 0x0100, 0x0008, 0x007c)
+        };
+        return Registers { af, bc, de, hl };
+    }
+}
+
+impl Default for Model {
+    fn default() -> Model {
+        return Model::Dmg;
+    }
+}
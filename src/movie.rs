@@ -0,0 +1,157 @@
+/*
+input movies, the buttons held in every frame from a known start so a
+run can be replayed exactly. all values little endian:
+
+    magic    "GBMV"
+    version  u16
+    rom      u32 crc32 of the rom the movie was made with
+    model    u8, see Model::id
+    start    u8, 0 = power on, 1 = a save state follows
+    state    u32 length + save state, only when start is 1
+    frames   u32
+    inputs   one byte per frame, the joypad:: button bits
+
+replay is deterministic because the core has no inputs besides the
+joypad: the machine starts from zeroed memory or the saved state, frames
+are a fixed number of cycles and there is no real time clock. a link
+cable to another emulator is the exception, movies are made without one
+*/
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+use crate::cpu::Z80;
+use crate::crc32::crc32;
+use crate::mmu::MMU;
+use crate::model::Model;
+use crate::rom_loader;
+use crate::savestate::{self, invalid, StateReader};
+
+pub const MAGIC: &[u8; 4] = b"GBMV";
+pub const VERSION: u16 = 1;
+
+pub struct Movie {
+    rom_crc: u32,
+    model: Model,
+    start: Option<Vec<u8>>,
+    inputs: Vec<u8>
+}
+
+impl Movie {
+    // a movie starting with the machine switched on
+    pub fn power_on(rom: &[u8], model: Model) -> Movie {
+        return Movie { rom_crc: crc32(rom), model, start: None, inputs: Vec::new() };
+    }
+
+    // a movie starting from where `cpu` is now
+    pub fn from_state(rom: &[u8], cpu: &Z80) -> Movie {
+        let model = cpu.memory().model();
+        return Movie { rom_crc: crc32(rom), model, start: Some(savestate::save(cpu)), inputs: Vec::new() };
+    }
+
+    pub fn model(&self) -> Model {
+        return self.model;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.inputs.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.inputs.is_empty();
+    }
+
+    pub fn input(&self, frame: usize) -> Option<u8> {
+        return self.inputs.get(frame).copied();
+    }
+
+    // builds the machine at the start of the movie, `rom` must be the one it was made with
+    pub fn machine(&self, rom: &[u8]) -> Result<Z80> {
+        if crc32(rom) != self.rom_crc {
+            return Err(invalid(&format!("movie was made with a different rom (crc32 {:08x})", self.rom_crc)));
+        }
+        let mut memory_unit = MMU::new();
+        rom_loader::insert_rom(rom, &mut memory_unit, Some(self.model));
+        let mut cpu = Z80::new(memory_unit);
+        cpu.reset();
+        if let Some(state) = &self.start {
+            savestate::load(&mut cpu, state)?;
+        }
+        return Ok(cpu);
+    }
+
+    // runs a frame with `buttons` held and adds it to the movie
+    pub fn record_frame(&mut self, cpu: &mut Z80, buttons: u8) {
+        cpu.memory_mut().set_buttons(buttons);
+        cpu.run_frame();
+        self.inputs.push(buttons);
+    }
+
+    // runs frame `frame` of the movie, false once it is over
+    pub fn play_frame(&self, cpu: &mut Z80, frame: usize) -> bool {
+        let buttons = match self.input(frame) {
+            Some(buttons) => buttons,
+            None => return false
+        };
+        cpu.memory_mut().set_buttons(buttons);
+        cpu.run_frame();
+        return true;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&self.rom_crc.to_le_bytes());
+        data.push(self.model.id());
+        match &self.start {
+            None => data.push(0),
+            Some(state) => {
+                data.push(1);
+                data.extend_from_slice(&(state.len() as u32).to_le_bytes());
+                data.extend_from_slice(state);
+            }
+        }
+        data.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.inputs);
+        return data;
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie> {
+        let mut reader = StateReader::new(data);
+        let mut magic = [0; 4];
+        for b in magic.iter_mut() {
+            *b = reader.read_u8()?;
+        }
+        if &magic != MAGIC {
+            return Err(invalid("not a movie"));
+        }
+        let version = reader.read_u16()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported movie version {}", version)));
+        }
+        let rom_crc = reader.read_u32()?;
+        let model = match Model::from_id(reader.read_u8()?) {
+            Some(model) => model,
+            None => return Err(invalid("movie has an unknown model"))
+        };
+        let start = match reader.read_u8()? {
+            0 => None,
+            1 => Some(reader.read_bytes()?.to_vec()),
+            _ => return Err(invalid("movie has an unknown start"))
+        };
+        let inputs = reader.read_bytes()?.to_vec();
+        if !reader.is_empty() {
+            return Err(invalid("movie has trailing data"));
+        }
+        return Ok(Movie { rom_crc, model, start, inputs });
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        return fs::write(path, self.to_bytes());
+    }
+
+    pub fn load(path: &Path) -> Result<Movie> {
+        return Movie::from_bytes(&fs::read(path)?);
+    }
+}
@@ -0,0 +1,117 @@
+/*
+turns what the ppu produces into the 160x144 rgba frame handed to pixels
+
+in dmg mode the ppu outputs shades 0-3 (0 lightest) after BGP/OBP0/OBP1
+have been applied, and a Palette picks the rgb of each shade. in cgb mode
+it outputs rgb555 colours from palette ram, which look washed out when
+shown as is on a modern screen, so they go through a colour correction
+curve that mimics the cgb lcd
+*/
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+
+pub type Rgba = [u8; 4];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Palette {
+    // the green of the original dmg screen
+    DmgGreen,
+    // the greys of the game boy pocket
+    Pocket,
+    // four user colours, lightest first
+    Custom([Rgba; 4])
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorCorrection {
+    // rgb555 scaled straight to rgb888
+    None,
+    // darker, less saturated colours like the cgb lcd
+    Cgb
+}
+
+fn rgb(color: u32) -> Rgba {
+    return [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xff];
+}
+
+impl Palette {
+    pub fn colors(&self) -> [Rgba; 4] {
+        return match self {
+            Palette::DmgGreen => [rgb(0x9bbc0f), rgb(0x8bac0f), rgb(0x306230), rgb(0x0f380f)],
+            Palette::Pocket => [rgb(0xffffff), rgb(0xa9a9a9), rgb(0x545454), rgb(0x000000)],
+            Palette::Custom(colors) => *colors
+        };
+    }
+
+    pub fn color(&self, shade: u8) -> Rgba {
+        return self.colors()[(shade & 3) as usize];
+    }
+
+    /*
+    "green", "pocket", or four comma separated hex colours lightest first,
+    like "e0f8d0,88c070,346856,081820"
+    */
+    pub fn parse(text: &str) -> Option<Palette> {
+        match text {
+            "green" | "dmg" => return Some(Palette::DmgGreen),
+            "pocket" | "grey" | "gray" => return Some(Palette::Pocket),
+            _ => {}
+        }
+
+        let parts: Vec<&str> = text.split(',').map(|p| p.trim().trim_start_matches('#')).collect();
+        if parts.len() != 4 { return None; }
+        let mut colors = [[0; 4]; 4];
+        for (color, part) in colors.iter_mut().zip(parts.iter()) {
+            if part.len() != 6 { return None; }
+            *color = rgb(u32::from_str_radix(part, 16).ok()?);
+        }
+        return Some(Palette::Custom(colors));
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        return Palette::DmgGreen;
+    }
+}
+
+// the shade a dmg palette register (BGP, OBP0, OBP1) gives colour number `color`
+pub fn shade(register: u8, color: u8) -> u8 {
+    return (register >> ((color & 3) * 2)) & 3;
+}
+
+/*
+converts an rgb555 colour (red in the low bits, as stored in cgb palette
+ram). the correction mixes the channels and caps them below full
+brightness the way the cgb screen does
+*/
+pub fn rgb555_to_rgba(color: u16, correction: ColorCorrection) -> Rgba {
+    let r = (color & 0x1f) as u32;
+    let g = ((color >> 5) & 0x1f) as u32;
+    let b = ((color >> 10) & 0x1f) as u32;
+
+    return match correction {
+        ColorCorrection::None => [((r << 3) | (r >> 2)) as u8, ((g << 3) | (g >> 2)) as u8, ((b << 3) | (b >> 2)) as u8, 0xff],
+        ColorCorrection::Cgb => {
+            let cr = (r * 26 + g * 4 + b * 2).min(960) >> 2;
+            let cg = (g * 24 + b * 8).min(960) >> 2;
+            let cb = (r * 6 + g * 4 + b * 22).min(960) >> 2;
+            [cr as u8, cg as u8, cb as u8, 0xff]
+        }
+    };
+}
+
+// fills `frame` (WIDTH * HEIGHT * 4 rgba bytes) from one dmg shade per pixel
+pub fn render_dmg(shades: &[u8], palette: &Palette, frame: &mut [u8]) {
+    let colors = palette.colors();
+    for (pixel, shade) in frame.chunks_exact_mut(4).zip(shades.iter()) {
+        pixel.copy_from_slice(&colors[(shade & 3) as usize]);
+    }
+}
+
+// fills `frame` from one rgb555 colour per pixel
+pub fn render_cgb(colors: &[u16], correction: ColorCorrection, frame: &mut [u8]) {
+    for (pixel, color) in frame.chunks_exact_mut(4).zip(colors.iter()) {
+        pixel.copy_from_slice(&rgb555_to_rgba(*color, correction));
+    }
+}
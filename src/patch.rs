@@ -0,0 +1,242 @@
+/*
+rom patches in the three formats hacks and translations ship in:
+
+    ips  "PATCH", then records of a 24 bit offset, a 16 bit size and
+         that many bytes (or, with size 0, a 16 bit count and one byte
+         repeated), until "EOF" and an optional 24 bit truncated size.
+         all big endian and no checksums
+
+    ups  "UPS1", the source and target sizes as varints, then hunks of a
+         varint skip followed by bytes xor'd into the target up to a 0.
+         ends with the crc32 of the source, the target and the patch
+
+    bps  "BPS1", source size, target size and metadata, then actions
+         that copy from the source, the patch or earlier parts of the
+         target. ends with the same three crc32s as ups
+
+the ups and bps checksums are checked so a patch made for a different
+dump of the game is refused instead of producing a broken rom. sizes
+and offsets are checked before anything is copied, a patched rom can be
+no bigger than archive::MAX_ROM_SIZE
+*/
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use crate::archive::MAX_ROM_SIZE;
+use crate::crc32::crc32;
+use crate::savestate::invalid;
+
+pub const EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Ips,
+    Ups,
+    Bps
+}
+
+pub fn detect(patch: &[u8]) -> Option<Format> {
+    if patch.starts_with(b"PATCH") { return Some(Format::Ips); }
+    if patch.starts_with(b"UPS1") { return Some(Format::Ups); }
+    if patch.starts_with(b"BPS1") { return Some(Format::Bps); }
+    return None;
+}
+
+// a patch file with the rom's name and a patch extension next to it
+pub fn find_beside(rom: &Path) -> Option<PathBuf> {
+    return EXTENSIONS.iter().map(|ext| rom.with_extension(ext)).find(|p| p.is_file());
+}
+
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    return match detect(patch) {
+        Some(Format::Ips) => apply_ips(rom, patch),
+        Some(Format::Ups) => apply_ups(rom, patch),
+        Some(Format::Bps) => apply_bps(rom, patch),
+        None => Err(invalid("not an ips, ups or bps patch"))
+    };
+}
+
+fn truncated() -> std::io::Error {
+    return invalid("patch is truncated");
+}
+
+fn too_big() -> std::io::Error {
+    return invalid("patch writes past the end of the rom");
+}
+
+fn check_size(target_size: usize) -> Result<()> {
+    if target_size > MAX_ROM_SIZE {
+        return Err(invalid(&format!("patched rom would be {} bytes, more than {}", target_size, MAX_ROM_SIZE)));
+    }
+    return Ok(());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self.data.get(self.pos).ok_or_else(truncated)?;
+        self.pos += 1;
+        return Ok(b);
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or_else(truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or_else(truncated)?;
+        self.pos += len;
+        return Ok(bytes);
+    }
+
+    fn big_endian(&mut self, len: usize) -> Result<usize> {
+        return Ok(self.bytes(len)?.iter().fold(0, |n, b| (n << 8) | *b as usize));
+    }
+
+    // the varint of ups and bps, 7 bits a byte with the top bit ending it
+    fn varint(&mut self) -> Result<usize> {
+        let (mut value, mut shift) = (0usize, 1usize);
+        loop {
+            let b = self.byte()?;
+            value = value.checked_add((b & 0x7f) as usize * shift).ok_or_else(|| invalid("bad patch number"))?;
+            if b & 0x80 != 0 { return Ok(value); }
+            shift = shift.checked_mul(128).ok_or_else(|| invalid("bad patch number"))?;
+            value += shift;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut out = rom.to_vec();
+    let mut reader = Reader { data: patch, pos: 5 };
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == 0x454f46 { break; }
+        let size = reader.big_endian(2)?;
+        let (bytes, len) = if size == 0 {
+            let count = reader.big_endian(2)?;
+            (vec![reader.byte()?; count], count)
+        } else {
+            (reader.bytes(size)?.to_vec(), size)
+        };
+        if out.len() < offset + len {
+            check_size(offset + len)?;
+            out.resize(offset + len, 0);
+        }
+        out[offset..offset + len].copy_from_slice(&bytes);
+    }
+    if patch.len() - reader.pos >= 3 {
+        let size = reader.big_endian(3)?;
+        out.truncate(size);
+    }
+    return Ok(out);
+}
+
+// checks the three crc32s at the end of a ups or bps patch, returns the target's
+fn check_footer(rom: &[u8], patch: &[u8]) -> Result<u32> {
+    if patch.len() < 16 { return Err(truncated()); }
+    let footer = &patch[patch.len() - 12..];
+    let crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+    if crc32(&patch[..patch.len() - 4]) != crc(8) {
+        return Err(invalid("patch is corrupt, its crc32 does not match"));
+    }
+    if crc32(rom) != crc(0) {
+        return Err(invalid(&format!("patch is for a different rom (crc32 {:08x}, this one is {:08x})", crc(0), crc32(rom))));
+    }
+    return Ok(crc(4));
+}
+
+fn check_target(out: &[u8], expected: u32) -> Result<()> {
+    if crc32(out) != expected {
+        return Err(invalid("patched rom does not match the crc32 the patch expects"));
+    }
+    return Ok(());
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let target_crc = check_footer(rom, patch)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader { data: &patch[..end], pos: 4 };
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if source_size != rom.len() {
+        return Err(invalid("patch is for a rom of a different size"));
+    }
+    check_size(target_size)?;
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+    let mut pos: usize = 0;
+    while reader.pos < end {
+        pos = pos.checked_add(reader.varint()?).ok_or_else(too_big)?;
+        loop {
+            let b = reader.byte()?;
+            if b == 0 { break; }
+            if pos < out.len() { out[pos] ^= b; }
+            pos += 1;
+        }
+        pos += 1;
+    }
+    check_target(&out, target_crc)?;
+    return Ok(out);
+}
+
+// a signed offset, the sign in the low bit
+fn relative(position: usize, offset: usize, limit: usize) -> Result<usize> {
+    let distance = offset >> 1;
+    let moved = if offset & 1 != 0 { position.checked_sub(distance) } else { position.checked_add(distance) };
+    return moved.filter(|p| *p <= limit).ok_or_else(|| invalid("patch copies from outside the rom"));
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let target_crc = check_footer(rom, patch)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader { data: &patch[..end], pos: 4 };
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata = reader.varint()?;
+    reader.bytes(metadata)?;
+    if source_size != rom.len() {
+        return Err(invalid("patch is for a rom of a different size"));
+    }
+    check_size(target_size)?;
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let (mut source_pos, mut target_pos) = (0, 0);
+    let outside = || invalid("patch copies from outside the rom");
+    while reader.pos < end {
+        let action = reader.varint()?;
+        let len = (action >> 2) + 1;
+        if len > target_size - out.len() { return Err(too_big()); }
+        match action & 3 {
+            // source read, the bytes at the same place in the source
+            0 => {
+                let start = out.len();
+                let end = start.checked_add(len).ok_or_else(outside)?;
+                out.extend_from_slice(rom.get(start..end).ok_or_else(outside)?);
+            },
+            // target read, bytes from the patch
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // source copy, from anywhere in the source
+            2 => {
+                source_pos = relative(source_pos, reader.varint()?, rom.len())?;
+                let end = source_pos.checked_add(len).ok_or_else(outside)?;
+                out.extend_from_slice(rom.get(source_pos..end).ok_or_else(outside)?);
+                source_pos = end;
+            },
+            // target copy, from earlier in the target, one byte at a time since the ranges may overlap
+            _ => {
+                target_pos = relative(target_pos, reader.varint()?, out.len())?;
+                for _ in 0..len {
+                    let b = *out.get(target_pos).ok_or_else(outside)?;
+                    out.push(b);
+                    target_pos += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size { return Err(invalid("patch ends before the rom is complete")); }
+    check_target(&out, target_crc)?;
+    return Ok(out);
+}
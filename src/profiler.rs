@@ -0,0 +1,213 @@
+/*
+cycle profiler. every instruction's t cycles, counted as Z80::run_frame
+counts them (dma stalls and halts included, at least 4), go to its pc
+and bank, and to the function being run in a call tree built from the
+calls the cpu makes:
+
+    CALL / RST / interrupt   enters the function at the new pc, when the
+                             return address was pushed (conditional calls
+                             not taken push nothing)
+    leaving                  once sp rises above where the return address
+                             of a function was pushed the function is
+                             over, whether by RET, RETI, popping the
+                             address or moving sp
+
+results are a per function table (self and total cycles), a per bank
+table, the busiest instructions and a folded stack file, one line per
+call path with its self cycles, that flamegraph.pl and speedscope read:
+
+    root;Main;UpdateSprites 15320
+
+functions are named by their label when symbols are loaded, otherwise
+bank:address like 01:4a20
+*/
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+use crate::symbols::Symbols;
+
+const CALL_OPS: [u8; 5] = [0xc4, 0xcc, 0xcd, 0xd4, 0xdc];
+
+// calls deeper than this are counted in the deepest function
+pub const MAX_DEPTH: usize = 256;
+
+// what Z80::run did, for the profiler
+pub struct Step {
+    pub pc: u16,
+    pub bank: u16,
+    // the opcode run, None when an interrupt was taken or the cpu is halted
+    pub op: Option<u8>,
+    pub interrupt: bool,
+    pub cycles: u16,
+    pub sp_before: u16,
+    pub sp_after: u16,
+    // where the cpu goes next
+    pub next_pc: u16,
+    pub next_bank: u16
+}
+
+struct Node {
+    // bank and address of the function, None for the root
+    function: Option<(u16, u16)>,
+    parent: usize,
+    children: HashMap<(u16, u16), usize>,
+    cycles: u64
+}
+
+pub struct Function {
+    pub name: String,
+    // cycles in the function itself
+    pub own: u64,
+    // cycles in the function and everything it called
+    pub total: u64
+}
+
+pub struct Profiler {
+    nodes: Vec<Node>,
+    // the node of every function being run and the sp its return address is at
+    stack: Vec<(usize, u16)>,
+    pcs: HashMap<(u16, u16), u64>,
+    total: u64,
+    symbols: Option<Symbols>
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        let root = Node { function: None, parent: 0, children: HashMap::new(), cycles: 0 };
+        return Profiler { nodes: vec![root], stack: Vec::new(), pcs: HashMap::new(), total: 0, symbols: None };
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    fn current(&self) -> usize {
+        return self.stack.last().map(|(node, _)| *node).unwrap_or(0);
+    }
+
+    pub fn record(&mut self, step: Step) {
+        let cycles = step.cycles as u64;
+        let current = self.current();
+        self.nodes[current].cycles += cycles;
+        *self.pcs.entry((step.bank, step.pc)).or_insert(0) += cycles;
+        self.total += cycles;
+
+        while let Some((_, sp)) = self.stack.last() {
+            if step.sp_after <= *sp { break; }
+            self.stack.pop();
+        }
+
+        let calling = step.op.map(|op| CALL_OPS.contains(&op) || op & 0xc7 == 0xc7).unwrap_or(false);
+        let pushed = step.sp_after == step.sp_before.wrapping_sub(2);
+        if (step.interrupt || calling) && pushed && self.stack.len() < MAX_DEPTH {
+            let parent = self.current();
+            let function = (step.next_bank, step.next_pc);
+            let node = match self.nodes[parent].children.get(&function) {
+                Some(node) => *node,
+                None => {
+                    self.nodes.push(Node { function: Some(function), parent, children: HashMap::new(), cycles: 0 });
+                    let node = self.nodes.len() - 1;
+                    self.nodes[parent].children.insert(function, node);
+                    node
+                }
+            };
+            self.stack.push((node, step.sp_after));
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        return self.total;
+    }
+
+    pub fn name(&self, function: Option<(u16, u16)>) -> String {
+        let (bank, address) = match function {
+            Some(function) => function,
+            None => return String::from("root")
+        };
+        return match self.symbols.as_ref().and_then(|s| s.label(bank, address)) {
+            Some(label) => label.to_string(),
+            None => format!("{:02X}:{:04X}", bank, address)
+        };
+    }
+
+    // the functions from `node` up to the root
+    fn path(&self, mut node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        while node != 0 {
+            node = self.nodes[node].parent;
+            path.push(node);
+        }
+        return path;
+    }
+
+    // cycles in each node and everything below it
+    fn subtree_cycles(&self) -> Vec<u64> {
+        let mut totals: Vec<u64> = self.nodes.iter().map(|n| n.cycles).collect();
+        // children are always added after their parents
+        for node in (1..self.nodes.len()).rev() {
+            totals[self.nodes[node].parent] += totals[node];
+        }
+        return totals;
+    }
+
+    // every function called, most total cycles first
+    pub fn functions(&self) -> Vec<Function> {
+        let subtree = self.subtree_cycles();
+        let mut functions: HashMap<Option<(u16, u16)>, (u64, u64)> = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let entry = functions.entry(node.function).or_insert((0, 0));
+            entry.0 += node.cycles;
+            // a recursive call is already in the total of the outer one
+            let recursive = self.path(i)[1..].iter().any(|n| self.nodes[*n].function == node.function);
+            if !recursive { entry.1 += subtree[i]; }
+        }
+        let mut functions: Vec<Function> = functions.into_iter()
+            .map(|(function, (own, total))| Function { name: self.name(function), own, total })
+            .collect();
+        functions.sort_by(|a, b| b.total.cmp(&a.total).then(b.own.cmp(&a.own)).then(a.name.cmp(&b.name)));
+        return functions;
+    }
+
+    // cycles spent running code from each bank, lowest bank first
+    pub fn banks(&self) -> Vec<(u16, u64)> {
+        let mut banks: HashMap<u16, u64> = HashMap::new();
+        for ((bank, _), cycles) in self.pcs.iter() {
+            *banks.entry(*bank).or_insert(0) += cycles;
+        }
+        let mut banks: Vec<(u16, u64)> = banks.into_iter().collect();
+        banks.sort();
+        return banks;
+    }
+
+    // cycles spent on the instruction at each bank and pc, most first
+    pub fn pcs(&self) -> Vec<((u16, u16), u64)> {
+        let mut pcs: Vec<((u16, u16), u64)> = self.pcs.iter().map(|(pc, cycles)| (*pc, *cycles)).collect();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        return pcs;
+    }
+
+    // the folded stacks, one "root;caller;callee cycles" line per call path
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self.nodes.iter().enumerate()
+            .filter(|(_, node)| node.cycles > 0)
+            .map(|(i, node)| {
+                let names: Vec<String> = self.path(i).iter().rev().map(|n| self.name(self.nodes[*n].function)).collect();
+                return format!("{} {}", names.join(";"), node.cycles);
+            })
+            .collect();
+        lines.sort();
+        return lines.iter().map(|line| format!("{}\n", line)).collect();
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        return fs::write(path, self.folded());
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        return Profiler::new();
+    }
+}
@@ -0,0 +1,170 @@
+/*
+rewind keeps the newest save state in full and every older one as a
+delta against its successor: the two states are xor'd together and the
+long runs of zeros (memory that did not change) are run length encoded.
+stepping back applies the newest delta to the newest state, so dropping
+the oldest entry when the ring buffer is full never invalidates the rest
+*/
+use std::collections::VecDeque;
+use std::io::Result;
+
+use crate::cpu::Z80;
+use crate::savestate;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>
+}
+
+impl Rewind {
+    // keeps up to `seconds` of history, snapshotting every `interval` frames
+    pub fn new(seconds: u32, interval: u32) -> Rewind {
+        let interval = interval.max(1);
+        let capacity = (seconds.saturating_mul(FRAMES_PER_SECOND) / interval) as usize;
+        return Rewind {
+            interval,
+            capacity,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new()
+        };
+    }
+
+    // call once per emulated frame
+    pub fn record_frame(&mut self, cpu: &Z80) {
+        if self.frames == 0 {
+            self.snapshot(cpu);
+        }
+        self.frames = (self.frames + 1) % self.interval;
+    }
+
+    pub fn snapshot(&mut self, cpu: &Z80) {
+        let state = savestate::save(cpu);
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode(&previous, &state));
+            while self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // number of snapshots that can still be restored
+    pub fn len(&self) -> usize {
+        return self.latest.is_some() as usize + self.deltas.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.latest.is_none();
+    }
+
+    pub fn seconds_available(&self) -> f32 {
+        return (self.len() as u32 * self.interval) as f32 / FRAMES_PER_SECOND as f32;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // restores the newest snapshot and forgets it, returns false once the history runs out
+    pub fn step_back(&mut self, cpu: &mut Z80) -> Result<bool> {
+        return Ok(self.rewind_snapshots(cpu, 1)? == 1);
+    }
+
+    // rolls back roughly `seconds`, returning how many frames were actually undone
+    pub fn rewind(&mut self, cpu: &mut Z80, seconds: f32) -> Result<u32> {
+        let frames = (seconds * FRAMES_PER_SECOND as f32).ceil() as u32;
+        let count = frames.div_ceil(self.interval).max(1) as usize;
+        return Ok(self.rewind_snapshots(cpu, count)? as u32 * self.interval);
+    }
+
+    fn rewind_snapshots(&mut self, cpu: &mut Z80, count: usize) -> Result<usize> {
+        let mut state = match self.latest.take() {
+            None => return Ok(0),
+            Some(s) => s
+        };
+
+        let mut restored = 1;
+        while restored < count {
+            match self.deltas.pop_back() {
+                None => break,
+                Some(delta) => state = decode(&state, &delta)
+            }
+            restored += 1;
+        }
+        savestate::load(cpu, &state)?;
+
+        self.latest = self.deltas.pop_back().map(|delta| decode(&state, &delta));
+        self.frames = 0;
+        return Ok(restored);
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while *pos < data.len() {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 { break; }
+        shift += 7;
+    }
+    return value;
+}
+
+// delta layout: older length, then (zero run, literal count, literal bytes) pairs
+fn encode(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let len = older.len().max(newer.len());
+    let byte_at = |data: &[u8], i: usize| if i < data.len() { data[i] } else { 0 };
+    let xor: Vec<u8> = (0..len).map(|i| byte_at(older, i) ^ byte_at(newer, i)).collect();
+
+    let mut out = Vec::new();
+    write_varint(&mut out, older.len());
+    let mut i = 0;
+    while i < len {
+        let zeros_start = i;
+        while i < len && xor[i] == 0 { i += 1; }
+        let literal_start = i;
+        while i < len && xor[i] != 0 { i += 1; }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, i - literal_start);
+        out.extend_from_slice(&xor[literal_start..i]);
+    }
+    return out;
+}
+
+fn decode(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let older_len = read_varint(delta, &mut pos);
+    let mut out = newer.to_vec();
+    out.resize(older_len.max(newer.len()), 0);
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let literals = read_varint(delta, &mut pos);
+        for byte in &delta[pos..pos + literals] {
+            out[i] ^= byte;
+            i += 1;
+        }
+        pos += literals;
+    }
+    out.truncate(older_len);
+    return out;
+}
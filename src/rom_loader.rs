@@ -1,5 +1,119 @@
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+use crate::archive;
 use crate::mmu::MMU;
+use crate::model::Model;
+use crate::patch;
+
+// reads a rom, unpacking it from a zip or gzip archive and applying the
+// ips, ups or bps patch beside it if there is one
+pub fn read_rom(path: &Path) -> Result<Vec<u8>> {
+    return read_rom_with(path, None, None);
+}
+
+// reads a rom, taking `entry` from a zip and applying `patch`, or the patch beside it when None
+pub fn read_rom_with(path: &Path, entry: Option<&str>, patch: Option<&Path>) -> Result<Vec<u8>> {
+    let rom = archive::unpack(fs::read(path)?, entry)?;
+    let patch = match patch {
+        Some(patch) => patch.to_path_buf(),
+        None => match patch::find_beside(path) {
+            Some(patch) => patch,
+            None => return Ok(rom)
+        }
+    };
+    return patch::apply(&rom, &fs::read(&patch)?)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", patch.display(), e)));
+}
+
+// puts `rom` in memory and sets up `model`, or the one the header asks for when None
+pub fn insert_rom(rom: &[u8], memory_unit: &mut MMU, model: Option<Model>) -> Model {
+    memory_unit.load_rom(rom);
+    let header = Header::parse(rom);
+    let model = model.unwrap_or_else(|| header.as_ref().map(Model::auto).unwrap_or_default());
+    memory_unit.set_model(model, model.cgb_mode(header.as_ref()));
+    return model;
+}
+
+pub fn load_rom(path: &Path, memory_unit: &mut MMU, model: Option<Model>) -> Result<Vec<u8>> {
+    let rom = read_rom(path)?;
+    insert_rom(&rom, memory_unit, model);
+    return Ok(rom);
+}
+
+/*
+cartridge header, 0x0100-0x014f of every rom:
+
+    0x0134-0x0143  title (the last bytes double as the cgb flag on newer carts)
+    0x0143         cgb flag, 0x80 = cgb enhanced, 0xc0 = cgb only
+    0x0146         sgb flag, 0x03 = sgb functions
+    0x0147         cartridge type (mbc, ram, battery...)
+    0x0148         rom size, 32KB << n
+    0x0149         ram size code
+    0x014b         old licensee code, must be 0x33 for sgb functions
+    0x014c         mask rom version
+    0x014d         header checksum over 0x0134-0x014c
+    0x014e-0x014f  global checksum, big endian
+*/
+pub struct Header {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub old_licensee: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_checksum: u8
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Option<Header> {
+        if rom.len() < 0x150 { return None; }
+
+        let title_end = if rom[0x143] & 0x80 != 0 { 0x143 } else { 0x144 };
+        let title: String = rom[0x134..title_end].iter()
+            .take_while(|b| **b != 0)
+            .map(|b| *b as char)
+            .collect();
+        let computed_checksum = rom[0x134..0x14d].iter()
+            .fold(0u8, |sum, b| sum.wrapping_sub(*b).wrapping_sub(1));
+
+        return Some(Header {
+            title: title.trim_end().to_string(),
+            cgb_flag: rom[0x143],
+            sgb_flag: rom[0x146],
+            cartridge_type: rom[0x147],
+            rom_size: rom[0x148],
+            ram_size: rom[0x149],
+            old_licensee: rom[0x14b],
+            version: rom[0x14c],
+            header_checksum: rom[0x14d],
+            global_checksum: ((rom[0x14e] as u16) << 8) | rom[0x14f] as u16,
+            computed_checksum
+        });
+    }
+
+    pub fn supports_cgb(&self) -> bool {
+        return self.cgb_flag & 0x80 != 0;
+    }
+
+    pub fn cgb_only(&self) -> bool {
+        return self.cgb_flag == 0xc0;
+    }
+
+    pub fn supports_sgb(&self) -> bool {
+        return self.sgb_flag == 0x03 && self.old_licensee == 0x33;
+    }
+
+    pub fn rom_bytes(&self) -> usize {
+        return 0x8000 << self.rom_size.min(8);
+    }
 
-pub fn load_rom(&mut memory_unit: MMU) {
-    
-}
\ No newline at end of file
+    pub fn header_checksum_ok(&self) -> bool {
+        return self.header_checksum == self.computed_checksum;
+    }
+}
@@ -0,0 +1,306 @@
+/*
+save state format, all values little endian:
+
+    magic    "GBSS"
+    version  u16
+    sections repeated until the end of the file:
+        tag      4 bytes ("CPU ", "MMU ", "SIO ", "JOY ", "LCD ", "MBC "...)
+        length   u32
+        payload  length bytes
+
+every component that holds machine state implements Savable and owns
+the layout of its own section payload. the version only changes when
+the layout of a section does. unknown sections are skipped and sections
+added since are optional, so a state from a build with extra
+peripherals still loads the parts we understand and an older state
+leaves the newer parts at power on
+*/
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::cpu::Z80;
+use crate::joypad::Joypad;
+use crate::mmu::LY_ADDRESS;
+
+pub const MAGIC: &[u8; 4] = b"GBSS";
+pub const VERSION: u16 = 3;
+pub const SLOT_COUNT: u8 = 10;
+
+pub const CPU_TAG: &[u8; 4] = b"CPU ";
+pub const MMU_TAG: &[u8; 4] = b"MMU ";
+pub const SERIAL_TAG: &[u8; 4] = b"SIO ";
+pub const JOYPAD_TAG: &[u8; 4] = b"JOY ";
+pub const CGB_TAG: &[u8; 4] = b"CGB ";
+pub const SGB_TAG: &[u8; 4] = b"SGB ";
+pub const LCD_TAG: &[u8; 4] = b"LCD ";
+pub const MBC_TAG: &[u8; 4] = b"MBC ";
+pub const BOOT_TAG: &[u8; 4] = b"BOOT";
+
+pub trait Savable {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()>;
+}
+
+pub fn invalid(msg: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, msg.to_string());
+}
+
+pub struct StateWriter {
+    buf: Vec<u8>,
+    section_start: Option<usize>
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        return StateWriter { buf, section_start: None };
+    }
+
+    pub fn section<T: Savable>(&mut self, tag: &[u8; 4], component: &T) {
+        self.buf.extend_from_slice(tag);
+        self.buf.extend_from_slice(&[0; 4]);
+        self.section_start = Some(self.buf.len());
+        component.save_state(self);
+
+        let start = self.section_start.take().unwrap();
+        let len = (self.buf.len() - start) as u32;
+        self.buf[start - 4..start].copy_from_slice(&len.to_le_bytes());
+    }
+
+    pub fn write_u8(&mut self, value: u8) { self.buf.push(value); }
+
+    pub fn write_bool(&mut self, value: bool) { self.buf.push(value as u8); }
+
+    pub fn write_u16(&mut self, value: u16) { self.buf.extend_from_slice(&value.to_le_bytes()); }
+
+    pub fn write_u32(&mut self, value: u32) { self.buf.extend_from_slice(&value.to_le_bytes()); }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        return self.buf;
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        return StateWriter::new();
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        return StateReader { data, pos: 0 };
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(invalid("save state is truncated"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        return Ok(bytes);
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        return Ok(self.read_u8()? != 0);
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        return Ok(u16::from_le_bytes([b[0], b[1]]));
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        return Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u32()? as usize;
+        return self.take(len);
+    }
+
+    // reads exactly `out.len()` bytes, rejecting a blob of any other size
+    pub fn read_into(&mut self, out: &mut [u8]) -> Result<()> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != out.len() {
+            return Err(invalid("save state blob has the wrong size"));
+        }
+        out.copy_from_slice(bytes);
+        return Ok(());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.pos == self.data.len();
+    }
+}
+
+// splits a state into its sections after checking the header
+fn sections(data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut reader = StateReader::new(data);
+    if reader.take(4)? != MAGIC {
+        return Err(invalid("not a save state"));
+    }
+    let version = reader.read_u16()?;
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported save state version {}", version)));
+    }
+
+    let mut found = Vec::new();
+    while !reader.is_empty() {
+        let mut tag = [0; 4];
+        tag.copy_from_slice(reader.take(4)?);
+        let len = reader.read_u32()? as usize;
+        found.push((tag, reader.take(len)?));
+    }
+    return Ok(found);
+}
+
+fn load_section<T: Savable>(found: &[([u8; 4], &[u8])], tag: &[u8; 4], component: &mut T) -> Result<()> {
+    let payload = match found.iter().find(|(t, _)| t == tag) {
+        None => return Err(invalid(&format!("save state has no {:?} section", String::from_utf8_lossy(tag)))),
+        Some((_, payload)) => payload
+    };
+    let mut reader = StateReader::new(payload);
+    component.load_state(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(invalid("save state section has trailing data"));
+    }
+    return Ok(());
+}
+
+// the boot rom image, saved while it is mapped since it is gone after
+struct BootRom(Vec<u8>);
+
+impl Savable for BootRom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.0);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        self.0 = reader.read_bytes()?.to_vec();
+        return Ok(());
+    }
+}
+
+pub fn save(cpu: &Z80) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.section(CPU_TAG, cpu);
+    writer.section(MMU_TAG, &cpu.memory_unit);
+    writer.section(SERIAL_TAG, &cpu.memory_unit.serial);
+    writer.section(JOYPAD_TAG, &cpu.memory_unit.joypad);
+    writer.section(LCD_TAG, &cpu.memory_unit.lcd);
+    writer.section(MBC_TAG, &cpu.memory_unit.mbc);
+    if let Some(rom) = cpu.memory_unit.boot_rom() {
+        writer.section(BOOT_TAG, &BootRom(rom.to_vec()));
+    }
+    if let Some(cgb) = &cpu.memory_unit.cgb {
+        writer.section(CGB_TAG, cgb);
+    }
+    if let Some(sgb) = &cpu.memory_unit.sgb {
+        writer.section(SGB_TAG, sgb);
+    }
+    return writer.finish();
+}
+
+fn load_sections(cpu: &mut Z80, found: &[([u8; 4], &[u8])]) -> Result<()> {
+    load_section(found, CPU_TAG, cpu)?;
+    load_section(found, MMU_TAG, &mut cpu.memory_unit)?;
+
+    let has = |tag: &[u8; 4]| found.iter().any(|(t, _)| t == tag);
+    // sections added since the first states are optional, left at power on when missing
+    if has(JOYPAD_TAG) {
+        load_section(found, JOYPAD_TAG, &mut cpu.memory_unit.joypad)?;
+    } else {
+        cpu.memory_unit.joypad = Joypad::new();
+    }
+    if has(SERIAL_TAG) {
+        load_section(found, SERIAL_TAG, &mut cpu.memory_unit.serial)?;
+    } else {
+        cpu.memory_unit.serial.reset();
+    }
+    // states from before the lcd timing pick up at the start of the line in LY
+    if has(LCD_TAG) {
+        load_section(found, LCD_TAG, &mut cpu.memory_unit.lcd)?;
+    } else {
+        cpu.memory_unit.lcd.set_line(cpu.memory_unit.peek(LY_ADDRESS));
+    }
+    if has(MBC_TAG) {
+        load_section(found, MBC_TAG, &mut cpu.memory_unit.mbc)?;
+    } else {
+        cpu.memory_unit.mbc.reset();
+    }
+    if has(BOOT_TAG) {
+        let mut boot = BootRom(Vec::new());
+        load_section(found, BOOT_TAG, &mut boot)?;
+        cpu.memory_unit.set_boot_rom(boot.0);
+    } else {
+        cpu.memory_unit.unmap_boot_rom();
+    }
+
+    // the cgb and sgb sections are only there for states saved in those modes
+    cpu.memory_unit.set_cgb(has(CGB_TAG));
+    if let Some(cgb) = cpu.memory_unit.cgb.as_mut() {
+        load_section(found, CGB_TAG, cgb)?;
+    }
+    cpu.memory_unit.set_sgb(has(SGB_TAG));
+    if let Some(sgb) = cpu.memory_unit.sgb.as_mut() {
+        load_section(found, SGB_TAG, sgb)?;
+    }
+    return Ok(());
+}
+
+// a state that fails halfway through leaves the machine as it was
+pub fn load(cpu: &mut Z80, data: &[u8]) -> Result<()> {
+    let found = sections(data)?;
+    let backup = save(cpu);
+    if let Err(e) = load_sections(cpu, &found) {
+        load_sections(cpu, &sections(&backup)?)?;
+        return Err(e);
+    }
+    return Ok(());
+}
+
+// quick save slots live beside the rom (or in `dir` when given) as <rom name>.ss<n>
+pub fn slot_path(rom: &Path, dir: Option<&Path>, slot: u8) -> PathBuf {
+    let name = rom.file_stem().unwrap_or_default().to_string_lossy();
+    let file = format!("{}.ss{}", name, slot);
+    return match dir {
+        Some(d) => d.join(file),
+        None => rom.with_file_name(file)
+    };
+}
+
+pub fn save_slot(cpu: &Z80, rom: &Path, dir: Option<&Path>, slot: u8) -> Result<PathBuf> {
+    if slot >= SLOT_COUNT {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("no save slot {}", slot)));
+    }
+    let path = slot_path(rom, dir, slot);
+    fs::write(&path, save(cpu))?;
+    return Ok(path);
+}
+
+pub fn load_slot(cpu: &mut Z80, rom: &Path, dir: Option<&Path>, slot: u8) -> Result<PathBuf> {
+    if slot >= SLOT_COUNT {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("no save slot {}", slot)));
+    }
+    let path = slot_path(rom, dir, slot);
+    load(cpu, &fs::read(&path)?)?;
+    return Ok(path);
+}
@@ -0,0 +1,94 @@
+/*
+ram search, the usual way of finding where a game keeps its lives or
+money: snapshot every candidate address, play a bit, then keep only the
+addresses whose value changed the way the counter did, and repeat until
+a handful are left
+
+cartridge ram (0xa000-0xbfff), wram (0xc000-0xdfff) and hram
+(0xff80-0xfffe) are searched, as 8 bit values or 16 bit little endian
+values starting at each address. values are read through MMU::peek so
+they are what the cpu would see, in the currently mapped banks
+*/
+use crate::mmu::MMU;
+
+pub const RANGES: [(u16, u16); 3] = [(0xa000, 0xbfff), (0xc000, 0xdfff), (0xff80, 0xfffe)];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Width {
+    Byte,
+    Word
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    // the same as at the last snapshot or filter
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    // holds exactly this value
+    Equal(u16),
+    NotEqual(u16)
+}
+
+pub struct Search {
+    width: Width,
+    // candidate addresses and their values when last looked at
+    candidates: Vec<(u16, u16)>
+}
+
+fn value(mmu: &MMU, width: Width, address: u16) -> u16 {
+    return match width {
+        Width::Byte => mmu.peek(address) as u16,
+        Width::Word => mmu.peek(address) as u16 | ((mmu.peek(address + 1) as u16) << 8)
+    };
+}
+
+impl Search {
+    // starts a search with every address as a candidate
+    pub fn new(mmu: &MMU, width: Width) -> Search {
+        // a word must not run past the end of its range
+        let last = if width == Width::Word { 1 } else { 0 };
+        let candidates = RANGES.iter()
+            .flat_map(|(start, end)| *start..=*end - last)
+            .map(|address| (address, value(mmu, width, address)))
+            .collect();
+        return Search { width, candidates };
+    }
+
+    pub fn width(&self) -> Width {
+        return self.width;
+    }
+
+    // keeps the candidates that pass `filter`, returns how many are left
+    pub fn filter(&mut self, mmu: &MMU, filter: Filter) -> usize {
+        let width = self.width;
+        let mask = if width == Width::Byte { 0xff } else { 0xffff };
+        self.candidates = self.candidates.iter()
+            .map(|(address, before)| (*address, *before, value(mmu, width, *address)))
+            .filter(|(_, before, now)| match filter {
+                Filter::Unchanged => now == before,
+                Filter::Changed => now != before,
+                Filter::Increased => now > before,
+                Filter::Decreased => now < before,
+                Filter::Equal(v) => *now == v & mask,
+                Filter::NotEqual(v) => *now != v & mask
+            })
+            .map(|(address, _, now)| (address, now))
+            .collect();
+        return self.candidates.len();
+    }
+
+    // the (address, value) of every candidate left
+    pub fn results(&self) -> &[(u16, u16)] {
+        return &self.candidates;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.candidates.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.candidates.is_empty();
+    }
+}
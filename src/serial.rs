@@ -0,0 +1,237 @@
+/*
+serial port, SB (0xff01) holds the byte being shifted and SC (0xff02)
+starts a transfer with bit 7 and picks the clock with bit 0:
+
+    internal clock (SC = 0x81): we drive the 8192Hz clock, the byte is
+    exchanged with the other end after 8 bits (4096 cycles)
+
+    external clock (SC = 0x80): we wait until the other end drives the
+    clock, which may be never
+
+in cgb mode bit 1 of SC picks a 262144Hz internal clock, 8 bits in 128
+cycles. the port counts cpu cycles, so in double speed both clocks run
+twice as fast, as they do on the hardware
+
+when a transfer completes SB holds the byte received, bit 7 of SC is
+cleared and the serial interrupt is requested. whatever is plugged into
+the port implements LinkCable
+*/
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::savestate::{Savable, StateReader, StateWriter};
+
+const TRANSFER_CYCLES: u32 = 8 * 512;
+const FAST_TRANSFER_CYCLES: u32 = 8 * 16;
+// how often a pending external clock transfer checks the cable
+const POLL_CYCLES: u32 = 512;
+
+pub trait LinkCable {
+    // we drive the clock: send `out` and return the byte shifted in, None when nothing answered
+    fn transfer(&mut self, out: u8) -> Option<u8>;
+
+    // the other end drives the clock: if it has clocked a byte in, answer with `out` and return it
+    fn poll(&mut self, out: u8) -> Option<u8> {
+        let _ = out;
+        return None;
+    }
+}
+
+// a cable plugged back into the same port
+pub struct Loopback;
+
+impl LinkCable for Loopback {
+    fn transfer(&mut self, out: u8) -> Option<u8> {
+        return Some(out);
+    }
+}
+
+// writes every byte sent to `out`, used for test rom output
+pub struct Capture<W: Write> {
+    out: W
+}
+
+impl<W: Write> Capture<W> {
+    pub fn new(out: W) -> Capture<W> {
+        return Capture { out };
+    }
+}
+
+impl Capture<io::Stdout> {
+    pub fn stdout() -> Capture<io::Stdout> {
+        return Capture::new(io::stdout());
+    }
+}
+
+impl<W: Write> LinkCable for Capture<W> {
+    fn transfer(&mut self, out: u8) -> Option<u8> {
+        let _ = self.out.write_all(&[out]).and_then(|_| self.out.flush());
+        return None;
+    }
+}
+
+/*
+links two emulators over a local tcp socket. each clock the master
+sends [MASTER, byte] and the other end answers with [REPLY, its SB]
+*/
+const MASTER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+
+pub struct SocketLink {
+    stream: TcpStream,
+    reply_timeout: Duration
+}
+
+impl SocketLink {
+    // waits for the other emulator to connect to 127.0.0.1:`port`
+    pub fn listen(port: u16) -> io::Result<SocketLink> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        return SocketLink::new(stream);
+    }
+
+    pub fn connect(port: u16) -> io::Result<SocketLink> {
+        return SocketLink::new(TcpStream::connect(("127.0.0.1", port))?);
+    }
+
+    fn new(stream: TcpStream) -> io::Result<SocketLink> {
+        stream.set_nodelay(true)?;
+        return Ok(SocketLink { stream, reply_timeout: Duration::from_millis(200) });
+    }
+
+    fn read_message(&mut self, kind: u8) -> io::Result<u8> {
+        let mut message = [0; 2];
+        self.stream.read_exact(&mut message)?;
+        if message[0] != kind {
+            return Err(io::Error::new(ErrorKind::InvalidData, "unexpected link cable message"));
+        }
+        return Ok(message[1]);
+    }
+}
+
+impl LinkCable for SocketLink {
+    fn transfer(&mut self, out: u8) -> Option<u8> {
+        self.stream.set_nonblocking(false).ok()?;
+        self.stream.set_read_timeout(Some(self.reply_timeout)).ok()?;
+        self.stream.write_all(&[MASTER, out]).ok()?;
+        return self.read_message(REPLY).ok();
+    }
+
+    fn poll(&mut self, out: u8) -> Option<u8> {
+        self.stream.set_nonblocking(true).ok()?;
+        let mut peek = [0; 2];
+        match self.stream.peek(&mut peek) {
+            Ok(2) => {},
+            _ => return None
+        }
+        self.stream.set_nonblocking(false).ok()?;
+        let incoming = self.read_message(MASTER).ok()?;
+        self.stream.write_all(&[REPLY, out]).ok()?;
+        return Some(incoming);
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cycles: u32,
+    cable: Option<Box<dyn LinkCable>>,
+    // cgb mode, where SC has the clock speed bit
+    cgb: bool
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        return Serial { sb: 0, sc: 0, cycles: 0, cable: None, cgb: false };
+    }
+
+    // the registers as they are at power on, the cable stays plugged in
+    pub fn reset(&mut self) {
+        self.sb = 0;
+        self.sc = 0;
+        self.cycles = 0;
+    }
+
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        if !cgb { self.sc &= 0x81; }
+    }
+
+    pub fn connect(&mut self, cable: Option<Box<dyn LinkCable>>) {
+        self.cable = cable;
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        return match address {
+            0xff01 => self.sb,
+            _ if self.cgb => self.sc | 0x7c,
+            _ => self.sc | 0x7e
+        };
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xff01 => self.sb = value,
+            _ => {
+                self.sc = value & if self.cgb { 0x83 } else { 0x81 };
+                self.cycles = 0;
+            }
+        }
+    }
+
+    fn complete(&mut self, incoming: u8) -> bool {
+        self.sb = incoming;
+        self.sc &= 0x7f;
+        self.cycles = 0;
+        return true;
+    }
+
+    // advances the port by `cycles`, returns true when the serial interrupt should fire
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        if self.sc & 0x80 == 0 { return false; }
+        self.cycles += cycles;
+
+        if self.sc & 0x01 != 0 {
+            let transfer_cycles = if self.sc & 0x02 != 0 { FAST_TRANSFER_CYCLES } else { TRANSFER_CYCLES };
+            if self.cycles < transfer_cycles { return false; }
+            let incoming = match self.cable.as_mut() {
+                Some(cable) => cable.transfer(self.sb),
+                None => None
+            };
+            return self.complete(incoming.unwrap_or(0xff));
+        }
+
+        if self.cycles < POLL_CYCLES { return false; }
+        self.cycles = 0;
+        let sb = self.sb;
+        return match self.cable.as_mut().and_then(|cable| cable.poll(sb)) {
+            Some(incoming) => self.complete(incoming),
+            None => false
+        };
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        return Serial::new();
+    }
+}
+
+impl Savable for Serial {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.sb);
+        writer.write_u8(self.sc);
+        writer.write_u32(self.cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        let sb = reader.read_u8()?;
+        let sc = reader.read_u8()?;
+        let cycles = reader.read_u32()?;
+        self.sb = sb;
+        self.sc = sc;
+        self.cycles = cycles;
+        return Ok(());
+    }
+}
@@ -0,0 +1,423 @@
+/*
+super game boy, the snes cartridge adapter. games talk to it by toggling
+the two select lines of P1 (0xff00):
+
+    P14 and P15 low    reset, starts a packet
+    P14 low            a 0 bit
+    P15 low            a 1 bit
+    both high          between bits
+
+a packet is 128 bits sent lsb first followed by a 0 stop bit. the first
+byte of the first packet is the command (bits 3-7) and how many packets
+the command takes (bits 0-2)
+
+the game screen is split into 20x18 tiles that each use one of four sgb
+palettes, which the PAL and ATTR commands set. CHR_TRN and PCT_TRN copy
+4KB out of vram to set the tiles, map and palettes of the 256x224 border
+drawn around the game screen
+*/
+use std::io::Result;
+
+use crate::palettes::{self, ColorCorrection, Rgba};
+use crate::savestate::{invalid, Savable, StateReader, StateWriter};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 224;
+// where the game screen sits inside the border
+pub const SCREEN_X: usize = 48;
+pub const SCREEN_Y: usize = 40;
+
+const PACKET_SIZE: usize = 16;
+const ATTR_WIDTH: usize = palettes::WIDTH / 8;
+const ATTR_HEIGHT: usize = palettes::HEIGHT / 8;
+pub const TRANSFER_SIZE: usize = 0x1000;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mask {
+    Cancel,
+    // keep showing the last frame
+    Freeze,
+    Black,
+    // fill with colour 0 of palette 0
+    Color0
+}
+
+// the vram copy a CHR_TRN or PCT_TRN is waiting for
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transfer {
+    // border tiles 0x00-0x7f, or 0x80-0xff when true
+    Tiles(bool),
+    // border map and palettes
+    Picture
+}
+
+pub struct Sgb {
+    palettes: [[u16; 4]; 4],
+    attributes: Vec<u8>,
+    mask: Mask,
+    // border tiles in snes 4bpp format, 32 bytes each
+    border_tiles: Vec<u8>,
+    // 32x32 entries of tile number and attribute byte
+    border_map: Vec<u8>,
+    // border palettes 4-7, 16 colours each
+    border_palettes: Vec<u16>,
+    frozen: Vec<u8>,
+    players: u8,
+    player: u8,
+    transfer: Option<Transfer>,
+
+    // packet reception
+    p1: u8,
+    receiving: bool,
+    bits: usize,
+    packet: [u8; PACKET_SIZE],
+    command: Vec<u8>
+}
+
+fn rgb555(data: &[u8], i: usize) -> u16 {
+    return (data[i * 2] as u16 | ((data[i * 2 + 1] as u16) << 8)) & 0x7fff;
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        return Sgb {
+            palettes: [[0x7fff, 0x5294, 0x294a, 0x0000]; 4],
+            attributes: vec![0; ATTR_WIDTH * ATTR_HEIGHT],
+            mask: Mask::Cancel,
+            border_tiles: vec![0; TRANSFER_SIZE * 2],
+            border_map: vec![0; 0x800],
+            border_palettes: vec![0; 64],
+            frozen: vec![0; palettes::WIDTH * palettes::HEIGHT],
+            players: 1,
+            player: 0,
+            transfer: None,
+            p1: 0x30,
+            receiving: false,
+            bits: 0,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new()
+        };
+    }
+
+    pub fn mask(&self) -> Mask {
+        return self.mask;
+    }
+
+    // sgb palette 0-3 of the 8x8 tile at (x, y) of the game screen
+    pub fn attribute(&self, x: usize, y: usize) -> u8 {
+        return self.attributes[y * ATTR_WIDTH + x];
+    }
+
+    pub fn palette(&self, palette: u8) -> [u16; 4] {
+        return self.palettes[(palette & 3) as usize];
+    }
+
+    // the joypad a P1 read with both select lines high answers for, 0-3
+    pub fn player(&self) -> u8 {
+        return self.player;
+    }
+
+    pub fn take_transfer(&mut self) -> Option<Transfer> {
+        return self.transfer.take();
+    }
+
+    // a write to P1, only bits 4 and 5 matter here
+    pub fn write_p1(&mut self, value: u8) {
+        let lines = value & 0x30;
+        let previous = self.p1;
+        self.p1 = lines;
+
+        // multiplayer games cycle through the joypads by raising P15
+        if self.players > 1 && previous & 0x10 == 0 && lines & 0x10 != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+
+        if lines == 0 {
+            self.receiving = true;
+            self.bits = 0;
+            self.packet = [0; PACKET_SIZE];
+            return;
+        }
+        // a bit is taken when the line goes back high
+        if !self.receiving || lines != 0x30 || previous == 0x30 || previous == 0 { return; }
+
+        let bit = previous == 0x10;
+        if self.bits == PACKET_SIZE * 8 {
+            // the stop bit must be 0
+            self.receiving = false;
+            if !bit { self.packet_done(); }
+            return;
+        }
+        if bit { self.packet[self.bits / 8] |= 1 << (self.bits % 8); }
+        self.bits += 1;
+    }
+
+    fn packet_done(&mut self) {
+        if self.command.is_empty() && self.packet[0] & 0x07 == 0 { return; }
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x07) as usize;
+        if self.command.len() >= packets * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.run(&command);
+        }
+    }
+
+    fn run(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            MLT_REQ => {
+                self.players = match data[1] & 3 { 1 => 2, 3 => 4, _ => 1 };
+                self.player = 0;
+            },
+            CHR_TRN => self.transfer = Some(Transfer::Tiles(data[1] & 1 != 0)),
+            PCT_TRN => self.transfer = Some(Transfer::Picture),
+            MASK_EN => {
+                self.mask = match data[1] & 3 { 1 => Mask::Freeze, 2 => Mask::Black, 3 => Mask::Color0, _ => Mask::Cancel };
+            },
+            // sound, snes code uploads and the system palette commands are not emulated
+            _ => {}
+        }
+    }
+
+    // colour 0 is shared by all four palettes
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let colors = &data[1..];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = rgb555(colors, 0);
+        }
+        for i in 1..4 {
+            self.palettes[first][i] = rgb555(colors, i);
+            self.palettes[second][i] = rgb555(colors, i + 3);
+        }
+    }
+
+    fn fill(&mut self, x1: usize, y1: usize, x2: usize, y2: usize, palette: u8) {
+        for y in y1..=y2.min(ATTR_HEIGHT - 1) {
+            for x in x1..=x2.min(ATTR_WIDTH - 1) {
+                self.attributes[y * ATTR_WIDTH + x] = palette & 3;
+            }
+        }
+    }
+
+    /*
+    each 6 byte data set is a control byte (bit 0 inside, bit 1 the border
+    line, bit 2 outside), the three palettes and the block as x1 y1 x2 y2.
+    with only inside or only outside set the border follows that palette
+    */
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets = (data[1] & 0x1f) as usize;
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let (mut control, palettes) = (set[0] & 7, set[1]);
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+            let (inside, mut border, outside) = (palettes & 3, (palettes >> 2) & 3, (palettes >> 4) & 3);
+            if control == 1 { control |= 2; border = inside; }
+            if control == 4 { control |= 2; border = outside; }
+
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let within = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = match (within, edge) {
+                        (true, true) if control & 2 != 0 => border,
+                        (true, false) if control & 1 != 0 => inside,
+                        (false, _) if control & 4 != 0 => outside,
+                        _ => continue
+                    };
+                    self.attributes[y * ATTR_WIDTH + x] = palette;
+                }
+            }
+        }
+    }
+
+    // each byte is a line number (bits 0-4), palette (bits 5-6) and bit 7 set for a row, clear for a column
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for line in data[2..].iter().take(count) {
+            let (n, palette) = ((line & 0x1f) as usize, (line >> 5) & 3);
+            if line & 0x80 != 0 {
+                self.fill(0, n, ATTR_WIDTH - 1, n, palette);
+            } else {
+                self.fill(n, 0, n, ATTR_HEIGHT - 1, palette);
+            }
+        }
+    }
+
+    // splits the screen at one row (bit 6 set) or column, with a palette for each side and the line itself
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on) = (data[1] & 3, (data[1] >> 2) & 3, (data[1] >> 4) & 3);
+        let at = data[2] as usize;
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let n = if data[1] & 0x40 != 0 { y } else { x };
+                self.attributes[y * ATTR_WIDTH + x] = if n < at { before } else if n == at { on } else { after };
+            }
+        }
+    }
+
+    // a palette for each tile from (x, y) on, 4 per byte msb first, going right (or down when byte 5 is 1)
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = ((data[3] as usize) | ((data[4] as usize) << 8)).min(ATTR_WIDTH * ATTR_HEIGHT);
+        let vertical = data[5] & 1 != 0;
+        for i in 0..count {
+            let byte = match data.get(6 + i / 4) {
+                Some(byte) => *byte,
+                None => return
+            };
+            if x >= ATTR_WIDTH || y >= ATTR_HEIGHT { return; }
+            self.attributes[y * ATTR_WIDTH + x] = (byte >> (6 - (i % 4) * 2)) & 3;
+            if vertical {
+                y += 1;
+                if y == ATTR_HEIGHT { y = 0; x += 1; }
+            } else {
+                x += 1;
+                if x == ATTR_WIDTH { x = 0; y += 1; }
+            }
+        }
+    }
+
+    // finishes a CHR_TRN or PCT_TRN with the 4KB the game put in vram
+    pub fn complete_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        let data = &data[..TRANSFER_SIZE.min(data.len())];
+        match transfer {
+            Transfer::Tiles(high) => {
+                let start = if high { TRANSFER_SIZE } else { 0 };
+                self.border_tiles[start..start + data.len()].copy_from_slice(data);
+            },
+            Transfer::Picture => {
+                let map = data.len().min(0x800);
+                self.border_map[..map].copy_from_slice(&data[..map]);
+                for (i, color) in self.border_palettes.iter_mut().enumerate() {
+                    if 0x800 + i * 2 + 1 < data.len() { *color = rgb555(&data[0x800..], i); }
+                }
+            }
+        }
+    }
+
+    // colour index 0-15 of pixel (x, y) of a border tile
+    fn border_pixel(&self, tile: usize, x: usize, y: usize) -> usize {
+        let row = &self.border_tiles[tile * 32..tile * 32 + 32];
+        let bit = 7 - x;
+        let planes = [row[y * 2], row[y * 2 + 1], row[16 + y * 2], row[16 + y * 2 + 1]];
+        return planes.iter().enumerate().map(|(i, p)| (((p >> bit) & 1) as usize) << i).sum();
+    }
+
+    /*
+    draws the border and the game screen into `frame`, WIDTH * HEIGHT * 4
+    rgba bytes. `shades` is the 160x144 dmg output, one shade 0-3 per pixel
+    */
+    pub fn render(&mut self, shades: &[u8], frame: &mut [u8]) {
+        let rgba = |color: u16| -> Rgba { palettes::rgb555_to_rgba(color, ColorCorrection::None) };
+        let backdrop = rgba(self.palettes[0][0]);
+
+        for ty in 0..HEIGHT / 8 {
+            for tx in 0..WIDTH / 8 {
+                let entry = (ty * 32 + tx) * 2;
+                let (tile, attr) = (self.border_map[entry] as usize, self.border_map[entry + 1]);
+                let palette = ((attr >> 2) & 7).saturating_sub(4) as usize & 3;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let px = if attr & 0x40 != 0 { 7 - x } else { x };
+                        let py = if attr & 0x80 != 0 { 7 - y } else { y };
+                        let index = self.border_pixel(tile, px, py);
+                        let color = if index == 0 { backdrop } else { rgba(self.border_palettes[palette * 16 + index]) };
+                        let i = ((ty * 8 + y) * WIDTH + tx * 8 + x) * 4;
+                        frame[i..i + 4].copy_from_slice(&color);
+                    }
+                }
+            }
+        }
+
+        if self.mask == Mask::Cancel {
+            let len = self.frozen.len();
+            self.frozen.copy_from_slice(&shades[..len]);
+        }
+        for y in 0..palettes::HEIGHT {
+            for x in 0..palettes::WIDTH {
+                let color = match self.mask {
+                    Mask::Black => [0, 0, 0, 0xff],
+                    Mask::Color0 => backdrop,
+                    _ => {
+                        let palette = self.attribute(x / 8, y / 8) as usize;
+                        rgba(self.palettes[palette][(self.frozen[y * palettes::WIDTH + x] & 3) as usize])
+                    }
+                };
+                let i = ((SCREEN_Y + y) * WIDTH + SCREEN_X + x) * 4;
+                frame[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Sgb {
+        return Sgb::new();
+    }
+}
+
+impl Savable for Sgb {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for palette in self.palettes.iter() {
+            for color in palette.iter() {
+                writer.write_u16(*color);
+            }
+        }
+        writer.write_bytes(&self.attributes);
+        writer.write_u8(self.mask as u8);
+        writer.write_bytes(&self.border_tiles);
+        writer.write_bytes(&self.border_map);
+        for color in self.border_palettes.iter() {
+            writer.write_u16(*color);
+        }
+        writer.write_bytes(&self.frozen);
+        writer.write_u8(self.players);
+        writer.write_u8(self.player);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let mut loaded = Sgb::new();
+        for palette in loaded.palettes.iter_mut() {
+            for color in palette.iter_mut() {
+                *color = reader.read_u16()? & 0x7fff;
+            }
+        }
+        reader.read_into(&mut loaded.attributes)?;
+        loaded.mask = match reader.read_u8()? {
+            0 => Mask::Cancel,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(invalid("bad sgb mask"))
+        };
+        reader.read_into(&mut loaded.border_tiles)?;
+        reader.read_into(&mut loaded.border_map)?;
+        for color in loaded.border_palettes.iter_mut() {
+            *color = reader.read_u16()? & 0x7fff;
+        }
+        reader.read_into(&mut loaded.frozen)?;
+        loaded.players = reader.read_u8()?.clamp(1, 4);
+        loaded.player = reader.read_u8()? % loaded.players;
+        *self = loaded;
+        return Ok(());
+    }
+}
@@ -0,0 +1,138 @@
+/*
+decides when the emulation loop runs a frame. normal play is paced to the
+real game boy frame rate times a multiplier between 0.25 and 8, turbo
+runs frames as fast as the host can, and while paused frames only run
+one at a time when advanced
+
+the loop asks frames_due() how many frames to run now and wait() how
+long it may sleep afterwards. there is no sound yet, once there is,
+what is played at speeds other than 1x has to be resampled to keep its
+pitch
+*/
+use std::time::{Duration, Instant};
+
+use crate::cpu::{CYCLES_PER_FRAME, CYCLES_PER_SECOND};
+
+pub const MULTIPLIERS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+// frames run at most in one go to catch up after a stall, past that the schedule is reset
+const MAX_CATCH_UP: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Wait {
+    // run again straight away
+    Now,
+    Until(Instant),
+    // nothing to do until the user does something
+    Input
+}
+
+pub struct Speed {
+    multiplier: f32,
+    turbo: bool,
+    paused: bool,
+    advance: u32,
+    next_frame: Option<Instant>
+}
+
+impl Speed {
+    pub fn new() -> Speed {
+        return Speed { multiplier: 1.0, turbo: false, paused: false, advance: 0, next_frame: None };
+    }
+
+    pub fn multiplier(&self) -> f32 {
+        return self.multiplier;
+    }
+
+    // clamped to 0.25x-8x
+    pub fn set_multiplier(&mut self, multiplier: f32) {
+        self.multiplier = multiplier.clamp(MULTIPLIERS[0], MULTIPLIERS[MULTIPLIERS.len() - 1]);
+        self.next_frame = None;
+    }
+
+    // steps to the next multiplier in MULTIPLIERS
+    pub fn faster(&mut self) {
+        let next = MULTIPLIERS.iter().find(|m| **m > self.multiplier).copied();
+        self.set_multiplier(next.unwrap_or(self.multiplier));
+    }
+
+    pub fn slower(&mut self) {
+        let next = MULTIPLIERS.iter().rev().find(|m| **m < self.multiplier).copied();
+        self.set_multiplier(next.unwrap_or(self.multiplier));
+    }
+
+    pub fn turbo(&self) -> bool {
+        return self.turbo;
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.next_frame = None;
+    }
+
+    pub fn paused(&self) -> bool {
+        return self.paused;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.advance = 0;
+        self.next_frame = None;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.set_paused(!self.paused);
+    }
+
+    // runs one more frame while paused, pausing first if needed
+    pub fn advance_frame(&mut self) {
+        if !self.paused { self.set_paused(true); }
+        self.advance += 1;
+    }
+
+    // real time one frame takes at the current multiplier
+    pub fn frame_duration(&self) -> Duration {
+        let seconds = CYCLES_PER_FRAME as f64 / CYCLES_PER_SECOND as f64;
+        return Duration::from_secs_f64(seconds / self.multiplier as f64);
+    }
+
+    // how many frames the loop should run at `now`
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        if self.paused {
+            let frames = self.advance;
+            self.advance = 0;
+            return frames;
+        }
+        if self.turbo { return 1; }
+
+        let next = *self.next_frame.get_or_insert(now);
+        if now < next { return 0; }
+
+        let frame = self.frame_duration();
+        let behind = ((now - next).as_secs_f64() / frame.as_secs_f64()) as u32 + 1;
+        if behind > MAX_CATCH_UP {
+            // don't try to catch up after the window was dragged or the machine slept
+            self.next_frame = Some(now + frame);
+            return 1;
+        }
+        self.next_frame = Some(next + frame * behind);
+        return behind;
+    }
+
+    // how long the loop can sleep after running the frames due
+    pub fn wait(&self) -> Wait {
+        if self.paused {
+            return if self.advance > 0 { Wait::Now } else { Wait::Input };
+        }
+        if self.turbo { return Wait::Now; }
+        return match self.next_frame {
+            Some(next) => Wait::Until(next),
+            None => Wait::Now
+        };
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Speed {
+        return Speed::new();
+    }
+}
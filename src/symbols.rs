@@ -0,0 +1,90 @@
+/*
+symbol files in the format rgbds (rgblink -n) and most other game boy
+assemblers write, one label per line:
+
+    ; comments run to the end of the line
+    00:0150 Main
+    00:0156 Main.loop
+    01:4000 LoadTiles
+    00:c000 wBuffer
+
+the bank is the one mapped at the address when the label is used: the
+rom bank for 4000-7fff, the wram bank for d000-dfff (1 on a dmg) and 0
+for everything without banks. labels are matched against addresses with
+MMU::bank_at, so a label in rom bank 2 only shows while bank 2 is mapped
+*/
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use crate::savestate::invalid;
+
+#[derive(Clone, Default, Debug)]
+pub struct Symbols {
+    by_name: HashMap<String, (u16, u16)>,
+    by_address: HashMap<(u16, u16), String>
+}
+
+// the .sym file named like the rom beside it
+pub fn find_beside(rom: &Path) -> Option<PathBuf> {
+    return Some(rom.with_extension("sym")).filter(|p| p.is_file());
+}
+
+fn is_local(name: &str) -> bool {
+    return name.contains('.');
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        return Symbols::default();
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols> {
+        let mut symbols = Symbols::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let bad = || invalid(&format!("line {} of the symbol file is not bank:address label", number + 1));
+
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(bad)?;
+            let (bank, address) = location.split_once(':').ok_or_else(bad)?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| bad())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| bad())?;
+            symbols.add(name.trim(), bank, address);
+        }
+        return Ok(symbols);
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols> {
+        return Symbols::parse(&fs::read_to_string(path)?);
+    }
+
+    // where several labels share an address the first global one names it
+    pub fn add(&mut self, name: &str, bank: u16, address: u16) {
+        self.by_name.insert(name.to_string(), (bank, address));
+        let replace = match self.by_address.get(&(bank, address)) {
+            Some(existing) => is_local(existing) && !is_local(name),
+            None => true
+        };
+        if replace { self.by_address.insert((bank, address), name.to_string()); }
+    }
+
+    // the bank and address of a label
+    pub fn lookup(&self, name: &str) -> Option<(u16, u16)> {
+        return self.by_name.get(name).copied();
+    }
+
+    // the label at `address` with `bank` mapped there
+    pub fn label(&self, bank: u16, address: u16) -> Option<&str> {
+        return self.by_address.get(&(bank, address)).map(|name| name.as_str());
+    }
+
+    pub fn len(&self) -> usize {
+        return self.by_name.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.by_name.is_empty();
+    }
+}
@@ -0,0 +1,82 @@
+/*
+runs a community test rom to completion. blargg roms print their result
+over the serial port, which is captured through a serial::Capture cable,
+and the text ends in "Passed" or "Failed". mooneye roms execute LD B,B
+when they finish with the fibonacci numbers 3, 5, 8, 13, 21, 34 in B, C,
+D, E, H, L on success and 0x42 in all of them on failure
+*/
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::cpu::{Z80, CYCLES_PER_SECOND};
+use crate::mmu::MMU;
+use crate::model::Model;
+use crate::rom_loader;
+use crate::serial::Capture;
+
+pub enum Outcome {
+    Passed(String),
+    Failed(String),
+    TimedOut(String)
+}
+
+// collects what the rom sends over the serial port
+#[derive(Clone)]
+struct SerialOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SerialOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+fn mooneye_signature(cpu: &Z80) -> Option<bool> {
+    let regs = [cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l];
+    if regs == [3, 5, 8, 13, 21, 34] { return Some(true); }
+    if regs.iter().all(|r| *r == 0x42) { return Some(false); }
+    return None;
+}
+
+// runs `rom` for at most `timeout_seconds` of emulated time
+pub fn run(rom: &[u8], model: Option<Model>, timeout_seconds: u64) -> Outcome {
+    let timeout = timeout_seconds * CYCLES_PER_SECOND;
+    let output = SerialOutput(Rc::new(RefCell::new(Vec::new())));
+    let mut memory_unit = MMU::new();
+    rom_loader::insert_rom(rom, &mut memory_unit, model);
+    memory_unit.serial.connect(Some(Box::new(Capture::new(output.clone()))));
+    let mut cpu = Z80::new(memory_unit);
+    cpu.reset();
+
+    let mut sent = 0;
+    let mut cycles: u64 = 0;
+    while cycles < timeout {
+        let op = cpu.memory_unit.peek(cpu.pc);
+        cpu.run();
+        cycles += cpu.last_cycles().max(4) as u64;
+
+        // the text is only decoded when a byte arrived
+        let received = output.0.borrow().len();
+        if received != sent {
+            sent = received;
+            let serial = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+            if serial.contains("Passed") { return Outcome::Passed(serial); }
+            if serial.contains("Failed") { return Outcome::Failed(serial); }
+        }
+
+        if op == 0x40 {
+            match mooneye_signature(&cpu) {
+                Some(true) => return Outcome::Passed(String::from("mooneye signature")),
+                Some(false) => return Outcome::Failed(String::from("mooneye failure signature")),
+                None => {}
+            }
+        }
+    }
+    let serial = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+    return Outcome::TimedOut(serial);
+}
@@ -0,0 +1,71 @@
+/*
+per instruction trace in the format used by gameboy-doctor and most
+reference emulators, one line before each instruction executes:
+
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+
+given symbols, an instruction with a label gets a "Label:" line before
+it. the cli only hands the tracer symbols named with --sym, so a plain
+trace stays line for line comparable
+*/
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+
+use crate::cpu::Z80;
+use crate::symbols::Symbols;
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    range: Option<(u16, u16)>,
+    bank: Option<u16>,
+    symbols: Option<Symbols>
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(out: W) -> Tracer {
+        return Tracer { out: Box::new(out), range: None, bank: None, symbols: None };
+    }
+
+    pub fn to_file(path: &Path) -> Result<Tracer> {
+        return Ok(Tracer::new(BufWriter::new(File::create(path)?)));
+    }
+
+    // only trace instructions with start <= pc <= end
+    pub fn set_range(&mut self, range: Option<(u16, u16)>) {
+        self.range = range;
+    }
+
+    // only trace instructions fetched from this bank
+    pub fn set_bank(&mut self, bank: Option<u16>) {
+        self.bank = bank;
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    pub fn trace(&mut self, cpu: &Z80) -> Result<()> {
+        if let Some((start, end)) = self.range {
+            if cpu.pc < start || cpu.pc > end { return Ok(()); }
+        }
+        if let Some(bank) = self.bank {
+            if cpu.memory_unit.bank_at(cpu.pc) != bank { return Ok(()); }
+        }
+
+        if let Some(symbols) = &self.symbols {
+            if let Some(name) = symbols.label(cpu.memory_unit.bank_at(cpu.pc), cpu.pc) {
+                writeln!(self.out, "{}:", name)?;
+            }
+        }
+        let mem = |i: u16| cpu.memory_unit.peek(cpu.pc.wrapping_add(i));
+        writeln!(self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, cpu.pc, mem(0), mem(1), mem(2), mem(3))?;
+        return Ok(());
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        return self.out.flush();
+    }
+}
@@ -0,0 +1,71 @@
+/*
+zip and gzip archives built here around a small rom: which zip entry is
+taken, with and without a name asked for, gzip, and archives that hold
+no rom or one too big to be a rom
+*/
+use std::io::{Cursor, ErrorKind, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use zip::write::{FileOptions, ZipWriter};
+
+use gb_emulator::archive::{self, MAX_ROM_SIZE};
+
+fn rom() -> Vec<u8> {
+    return (0..0x200).map(|i| (i * 7) as u8).collect();
+}
+
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in files.iter() {
+        writer.start_file(*name, FileOptions::default()).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    return writer.finish().unwrap().into_inner();
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    return encoder.finish().unwrap();
+}
+
+fn error(result: std::io::Result<Vec<u8>>) -> String {
+    let e = result.expect_err("the archive should be refused");
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    return e.to_string();
+}
+
+#[test]
+fn raw_roms_pass_through() {
+    assert_eq!(archive::unpack(rom(), None).unwrap(), rom());
+}
+
+#[test]
+fn zip_entries() {
+    let other = vec![0x42; 0x10];
+    let data = zip(&[("readme.txt", b"not a rom"), ("game/Game.GBC", &rom()), ("game/other.gb", &other)]);
+
+    // the first rom when none is asked for
+    assert_eq!(archive::unpack(data.clone(), None).unwrap(), rom());
+    assert_eq!(archive::unpack(data.clone(), Some("game/other.gb")).unwrap(), other);
+    assert_eq!(archive::unpack(data.clone(), Some("other.gb")).unwrap(), other);
+    assert_eq!(archive::unpack(data.clone(), Some("readme.txt")).unwrap(), b"not a rom".to_vec());
+    assert!(error(archive::unpack(data, Some("missing.gb"))).contains("no file named missing.gb"));
+
+    let data = zip(&[("readme.txt", b"not a rom")]);
+    assert!(error(archive::unpack(data, None)).contains("no .gb or .gbc rom"));
+    let data = zip(&[("empty.gb", b"")]);
+    assert!(error(archive::unpack(data, None)).contains("no rom"));
+}
+
+#[test]
+fn gzip_files() {
+    assert_eq!(archive::unpack(gzip(&rom()), None).unwrap(), rom());
+    assert!(error(archive::unpack(gzip(&[]), None)).contains("no rom"));
+    assert!(error(archive::unpack(gzip(&vec![0; MAX_ROM_SIZE + 1]), None)).contains("too big"));
+
+    let mut truncated = gzip(&rom());
+    truncated.truncate(truncated.len() / 2);
+    archive::unpack(truncated, None).expect_err("a cut off gzip should be refused");
+}
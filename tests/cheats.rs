@@ -0,0 +1,57 @@
+/*
+gameshark and game genie codes decoded by hand from the formats in
+cheats.rs, then applied to a small rom: gameshark writes land in ram
+once a frame and game genie codes replace rom reads, only where the
+compare byte matches
+*/
+use gb_emulator::cheats::{Cheats, Code};
+use gb_emulator::mmu::MMU;
+
+#[test]
+fn gameshark_codes() {
+    assert_eq!(Code::parse("010238CD"), Some(Code::GameShark { bank: 0x01, value: 0x02, address: 0xcd38 }));
+    assert_eq!(Code::parse("816310a0"), Some(Code::GameShark { bank: 0x81, value: 0x63, address: 0xa010 }));
+    assert_eq!(Code::parse("01FF80FF"), Some(Code::GameShark { bank: 0x01, value: 0xff, address: 0xff80 }));
+
+    // the rom, the boot rom switch, the other registers and the echo of wram
+    for code in ["01010020", "01FF50FF", "018040FF", "010000E0", "0101FFFF"].iter() {
+        assert_eq!(Code::parse(code), None, "{}", code);
+    }
+    assert_eq!(Code::parse("010238C"), None);
+    assert_eq!(Code::parse("010238CG"), None);
+}
+
+#[test]
+fn game_genie_codes() {
+    // value 00 at (B ^ F)A17, compare C9 rotated right by 2 and xor'd with BA
+    assert_eq!(Code::parse("00A-17B-C49"), Some(Code::GameGenie { address: 0x4a17, value: 0x00, compare: Some(0xc8) }));
+    assert_eq!(Code::parse("3EF-0DF"), Some(Code::GameGenie { address: 0x0f0d, value: 0x3e, compare: None }));
+    // (7 ^ F)A17 is past the rom
+    assert_eq!(Code::parse("00A-177"), None);
+    assert_eq!(Code::parse("00A-17B-C4"), None);
+}
+
+#[test]
+fn applied_to_a_rom() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x4a17] = 0xc8;
+    rom[0x0f0d] = 0x11;
+    let mut memory_unit = MMU::new();
+    memory_unit.load_rom(&rom);
+
+    let mut cheats = Cheats::new();
+    cheats.add("010238CD").unwrap();
+    cheats.add("00A-17B-C49").unwrap();
+    let wrong_compare = cheats.add("3EF-0DF-A4A").unwrap();
+    assert!(cheats.add("01010020").is_err());
+    memory_unit.cheats = cheats;
+
+    memory_unit.apply_cheats();
+    assert_eq!(memory_unit.peek(0xcd38), 0x02);
+    assert_eq!(memory_unit.peek(0x4a17), 0x00);
+    assert_eq!(memory_unit.peek(0x0f0d), 0x11);
+
+    memory_unit.cheats.set_enabled(wrong_compare, false);
+    memory_unit.cheats.add("3EF-0DF").unwrap();
+    assert_eq!(memory_unit.peek(0x0f0d), 0x3e);
+}
@@ -0,0 +1,166 @@
+/*
+drives the gdb stub from a local client speaking the remote serial
+protocol, the way gdb would: registers, memory, breakpoints, watchpoints,
+stepping, ctrl-c and detach. the program in rom at 0x0100:
+
+    0100  3E 42     LD A,$42
+    0102  EA 00 C0  LD ($C000),A
+    0105  FA 00 C0  LD A,($C000)
+    0108  18 FE     JR $0108
+*/
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+
+use gb_emulator::cpu::Z80;
+use gb_emulator::gdb::Server;
+use gb_emulator::mmu::MMU;
+
+const PROGRAM: [u8; 10] = [0x3e, 0x42, 0xea, 0x00, 0xc0, 0xfa, 0x00, 0xc0, 0x18, 0xfe];
+
+struct Client {
+    stream: TcpStream
+}
+
+impl Client {
+    fn connect() -> Client {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut rom = vec![0; 0x8000];
+            rom[0x100..0x100 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+            let mut memory_unit = MMU::new();
+            memory_unit.load_rom(&rom);
+            let mut cpu = Z80::new(memory_unit);
+            cpu.reset();
+            let server = Server::bind(0).unwrap();
+            sender.send(server.port().unwrap()).unwrap();
+            server.serve(&mut cpu).unwrap();
+        });
+        let port = receiver.recv().unwrap();
+        return Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        return byte[0];
+    }
+
+    // sends a packet and returns the reply, acking both ways
+    fn request(&mut self, data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.stream.write_all(format!("${}#{:02x}", data, sum).as_bytes()).unwrap();
+        assert_eq!(self.byte(), b'+', "no ack for {}", data);
+        return self.reply();
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                b => data.push(b)
+            }
+        }
+        let checksum = [self.byte(), self.byte()];
+        let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", sum));
+        self.stream.write_all(b"+").unwrap();
+        return String::from_utf8(data).unwrap();
+    }
+
+    // pc out of a g reply, registers are af bc de hl sp pc little endian
+    fn pc(&mut self) -> u16 {
+        let registers = self.request("g");
+        return u16::from_str_radix(&format!("{}{}", &registers[22..24], &registers[20..22]), 16).unwrap();
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    let mut client = Client::connect();
+    assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+    assert!(client.request("qXfer:features:read:target.xml:0,1000").contains("name=\"pc\""));
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("g"), "b0011300d8004d01feff0001");
+    assert_eq!(client.request("p5"), "0001");
+
+    assert_eq!(client.request("P2=3412"), "OK");
+    assert_eq!(client.request("p2"), "3412");
+
+    assert_eq!(client.request("m100,3"), "3e42ea");
+    assert_eq!(client.request("Mc010,2:abcd"), "OK");
+    assert_eq!(client.request("mc010,2"), "abcd");
+    assert_eq!(client.request("mc010"), "E01");
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+    assert_eq!(client.request("D"), "OK");
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut client = Client::connect();
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.pc(), 0x0102);
+
+    assert_eq!(client.request("Z0,105,1"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.pc(), 0x0105);
+    assert_eq!(client.request("mc000,1"), "42");
+
+    assert_eq!(client.request("z0,105,1"), "OK");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.pc(), 0x0108);
+    assert_eq!(client.request("D"), "OK");
+}
+
+#[test]
+fn watchpoints() {
+    let mut client = Client::connect();
+    assert_eq!(client.request("Z2,c000,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:c000;");
+    assert_eq!(client.pc(), 0x0105);
+    assert_eq!(client.request("z2,c000,1"), "OK");
+
+    assert_eq!(client.request("Z3,c000,1"), "OK");
+    assert_eq!(client.request("c"), "T05rwatch:c000;");
+    assert_eq!(client.pc(), 0x0108);
+    assert_eq!(client.request("D"), "OK");
+}
+
+#[test]
+fn fetches_are_not_reads() {
+    let mut client = Client::connect();
+    // the opcode of LD ($C000),A is fetched but never read as data
+    assert_eq!(client.request("Z3,102,1"), "OK");
+    assert_eq!(client.request("Z0,108,1"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.pc(), 0x0108);
+    assert_eq!(client.request("D"), "OK");
+}
+
+#[test]
+fn memory_writes_are_not_watched() {
+    let mut client = Client::connect();
+    // gdb's own write is not the program's, only the LD ($C000),A stops
+    assert_eq!(client.request("Z2,c000,1"), "OK");
+    assert_eq!(client.request("Mc000,1:99"), "OK");
+    assert_eq!(client.request("c"), "T05watch:c000;");
+    assert_eq!(client.pc(), 0x0105);
+    assert_eq!(client.request("D"), "OK");
+}
+
+#[test]
+fn interrupt() {
+    let mut client = Client::connect();
+    // the program ends in a loop, only ctrl-c stops it
+    let sum = b'c';
+    client.stream.write_all(format!("$c#{:02x}", sum).as_bytes()).unwrap();
+    assert_eq!(client.byte(), b'+');
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+    assert_eq!(client.pc(), 0x0108);
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.stream.write_all(b"$k#6b").unwrap();
+}
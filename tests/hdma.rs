@@ -0,0 +1,98 @@
+/*
+hblank dma driven by the lcd timing in MMU::tick: one 16 byte block
+lands in vram at the start of each hblank while the lcd is on, and LY,
+STAT and the vblank interrupt follow the same clock. the cpu stall a
+block charges survives a save state
+*/
+use gb_emulator::cpu::Z80;
+use gb_emulator::lcd::{DOTS_PER_LINE, VISIBLE_LINES};
+use gb_emulator::mmu::{IF_ADDRESS, LCDC_ADDRESS, LY_ADDRESS, MMU, STAT_ADDRESS, VBLANK_INTERRUPT};
+use gb_emulator::model::Model;
+use gb_emulator::savestate;
+
+// a cgb with the lcd on at the start of line 0 and 32 bytes of tiles at 0xc000
+fn cgb() -> MMU {
+    let mut memory_unit = MMU::new();
+    memory_unit.set_model(Model::Cgb, true);
+    memory_unit.set_b(LCDC_ADDRESS, 0x91);
+    for i in 0..0x20 {
+        memory_unit.set_b(0xc000 + i, 0x10 + i as u8);
+    }
+    return memory_unit;
+}
+
+// starts a hblank transfer of `blocks` blocks from 0xc000 to 0x8000
+fn start_hblank_dma(memory_unit: &mut MMU, blocks: u8) {
+    for (address, value) in [(0xff51, 0xc0), (0xff52, 0x00), (0xff53, 0x00), (0xff54, 0x00)].iter() {
+        memory_unit.set_b(*address, *value);
+    }
+    memory_unit.set_b(0xff55, 0x80 | (blocks - 1));
+}
+
+fn vram(memory_unit: &MMU, start: u16) -> Vec<u8> {
+    return (start..start + 0x10).map(|a| memory_unit.peek(a)).collect();
+}
+
+#[test]
+fn hblank_dma_copies_a_block_per_hblank() {
+    let mut memory_unit = cgb();
+    start_hblank_dma(&mut memory_unit, 2);
+    let first: Vec<u8> = (0x10..0x20).collect();
+    let second: Vec<u8> = (0x20..0x30).collect();
+
+    // modes 2 and 3 of line 0, no hblank yet
+    memory_unit.tick(251);
+    assert_eq!(vram(&memory_unit, 0x8000), vec![0; 0x10]);
+    assert_eq!(memory_unit.peek(0xff55), 0x01);
+
+    memory_unit.tick(1);
+    assert_eq!(memory_unit.peek(STAT_ADDRESS) & 0x03, 0);
+    assert_eq!(vram(&memory_unit, 0x8000), first);
+    assert_eq!(vram(&memory_unit, 0x8010), vec![0; 0x10]);
+    assert_eq!(memory_unit.peek(0xff55), 0x00);
+    assert!(memory_unit.take_dma_stall() > 0);
+
+    memory_unit.tick(DOTS_PER_LINE);
+    assert_eq!(memory_unit.peek(LY_ADDRESS), 1);
+    assert_eq!(vram(&memory_unit, 0x8010), second);
+    assert_eq!(memory_unit.peek(0xff55), 0xff);
+}
+
+#[test]
+fn hblank_dma_waits_while_the_lcd_is_off() {
+    let mut memory_unit = cgb();
+    memory_unit.set_b(LCDC_ADDRESS, 0x11);
+    start_hblank_dma(&mut memory_unit, 1);
+    memory_unit.tick(DOTS_PER_LINE * 2);
+    assert_eq!(memory_unit.peek(LY_ADDRESS), 0);
+    assert_eq!(vram(&memory_unit, 0x8000), vec![0; 0x10]);
+
+    memory_unit.set_b(LCDC_ADDRESS, 0x91);
+    memory_unit.tick(DOTS_PER_LINE);
+    assert_eq!(vram(&memory_unit, 0x8000), (0x10..0x20).collect::<Vec<u8>>());
+}
+
+#[test]
+fn vblank_starts_at_line_144() {
+    let mut memory_unit = cgb();
+    memory_unit.tick(DOTS_PER_LINE * VISIBLE_LINES - 4);
+    assert_eq!(memory_unit.peek(IF_ADDRESS) & VBLANK_INTERRUPT, 0);
+    memory_unit.tick(4);
+    assert_eq!(memory_unit.peek(LY_ADDRESS), 144);
+    assert_eq!(memory_unit.peek(STAT_ADDRESS) & 0x03, 1);
+    assert_ne!(memory_unit.peek(IF_ADDRESS) & VBLANK_INTERRUPT, 0);
+}
+
+#[test]
+fn stall_is_saved() {
+    let mut memory_unit = cgb();
+    start_hblank_dma(&mut memory_unit, 2);
+    memory_unit.tick(252);
+    let cpu = Z80::new(memory_unit);
+    let state = savestate::save(&cpu);
+
+    let mut other = Z80::new(cgb());
+    savestate::load(&mut other, &state).unwrap();
+    assert_eq!(other.memory_mut().take_dma_stall(), 32);
+    assert_eq!(other.memory().peek(0xff55), 0x00);
+}
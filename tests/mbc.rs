@@ -0,0 +1,91 @@
+/*
+cartridges built here with a header asking for an mbc and every rom
+bank filled with its own number, so a read of 0x4000 tells which bank
+is mapped: writes to the rom set the bank registers and never change
+the rom, and cartridge ram only answers once it is enabled
+*/
+use gb_emulator::mbc::{Kind, ROM_BANK_SIZE};
+use gb_emulator::mmu::MMU;
+
+fn cartridge(cartridge_type: u8, banks: usize, ram_size: u8) -> MMU {
+    let mut rom: Vec<u8> = (0..banks * ROM_BANK_SIZE).map(|i| (i / ROM_BANK_SIZE) as u8).collect();
+    rom[0x147] = cartridge_type;
+    rom[0x149] = ram_size;
+    let mut memory_unit = MMU::new();
+    memory_unit.load_rom(&rom);
+    return memory_unit;
+}
+
+#[test]
+fn rom_writes_do_not_change_the_rom() {
+    let mut memory_unit = cartridge(0x00, 2, 0);
+    assert_eq!(memory_unit.mbc.kind(), Kind::None);
+    memory_unit.set_b(0x2000, 0x05);
+    memory_unit.set_b(0x0100, 0x42);
+    assert_eq!(memory_unit.peek(0x0100), 0x00);
+    assert_eq!(memory_unit.peek(0x4000), 0x01);
+    assert_eq!(memory_unit.bank_at(0x4000), 1);
+    // and there is no ram to write to
+    memory_unit.set_b(0xa000, 0x42);
+    assert_eq!(memory_unit.peek(0xa000), 0xff);
+}
+
+#[test]
+fn mbc1_banks() {
+    let mut memory_unit = cartridge(0x01, 64, 0);
+    assert_eq!(memory_unit.mbc.kind(), Kind::Mbc1);
+    assert_eq!(memory_unit.peek(0x4000), 1);
+
+    memory_unit.set_b(0x2000, 0x05);
+    assert_eq!(memory_unit.peek(0x4000), 5);
+    assert_eq!(memory_unit.bank_at(0x4000), 5);
+    // bank 0 reads as bank 1
+    memory_unit.set_b(0x2000, 0x00);
+    assert_eq!(memory_unit.peek(0x4000), 1);
+
+    // the upper bits, which mode 1 also puts over 0x0000-0x3fff
+    memory_unit.set_b(0x2000, 0x03);
+    memory_unit.set_b(0x4000, 0x01);
+    assert_eq!(memory_unit.peek(0x4000), 0x23);
+    assert_eq!(memory_unit.peek(0x0200), 0x00);
+    memory_unit.set_b(0x6000, 0x01);
+    assert_eq!(memory_unit.peek(0x0200), 0x20);
+    assert_eq!(memory_unit.bank_at(0x0200), 0x20);
+}
+
+#[test]
+fn mbc5_banks() {
+    let mut memory_unit = cartridge(0x19, 512, 0);
+    memory_unit.set_b(0x2000, 0x00);
+    assert_eq!(memory_unit.bank_at(0x4000), 0);
+    memory_unit.set_b(0x2000, 0x34);
+    memory_unit.set_b(0x3000, 0x01);
+    assert_eq!(memory_unit.bank_at(0x4000), 0x134);
+    assert_eq!(memory_unit.peek(0x4000), 0x34);
+}
+
+#[test]
+fn cartridge_ram() {
+    // mbc3 with 32KB of ram
+    let mut memory_unit = cartridge(0x13, 4, 3);
+    memory_unit.set_b(0xa000, 0x42);
+    assert_eq!(memory_unit.peek(0xa000), 0xff);
+
+    memory_unit.set_b(0x0000, 0x0a);
+    memory_unit.set_b(0xa000, 0x42);
+    memory_unit.set_b(0x4000, 0x02);
+    assert_eq!(memory_unit.peek(0xa000), 0x00);
+    memory_unit.set_b(0xa000, 0x43);
+    assert_eq!(memory_unit.bank_at(0xa000), 2);
+    memory_unit.set_b(0x4000, 0x00);
+    assert_eq!(memory_unit.peek(0xa000), 0x42);
+
+    // the clock registers are not there
+    memory_unit.set_b(0x4000, 0x08);
+    assert_eq!(memory_unit.peek(0xa000), 0xff);
+
+    memory_unit.set_b(0x0000, 0x00);
+    memory_unit.set_b(0x4000, 0x02);
+    assert_eq!(memory_unit.peek(0xa000), 0xff);
+    assert_eq!(memory_unit.mbc.ram()[2 * 0x2000], 0x43);
+}
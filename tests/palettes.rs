@@ -0,0 +1,49 @@
+/*
+the rgba that lands in the frame for known dmg shades, after a palette
+register picked them, and for known cgb colours with and without the
+colour correction
+*/
+use gb_emulator::palettes::{self, ColorCorrection, Palette, HEIGHT, WIDTH};
+
+fn frame() -> Vec<u8> {
+    return vec![0; WIDTH * HEIGHT * 4];
+}
+
+#[test]
+fn dmg_shades() {
+    // BGP 0xe4 keeps colour n at shade n, 0x1b turns it around
+    let shades: Vec<u8> = (0..WIDTH * HEIGHT).map(|i| palettes::shade(0x1b, (i % 4) as u8)).collect();
+    assert_eq!(shades[..4].to_vec(), vec![3, 2, 1, 0]);
+    assert_eq!(palettes::shade(0xe4, 2), 2);
+
+    let mut out = frame();
+    palettes::render_dmg(&shades, &Palette::DmgGreen, &mut out);
+    assert_eq!(out[..8].to_vec(), vec![0x0f, 0x38, 0x0f, 0xff, 0x30, 0x62, 0x30, 0xff]);
+    assert_eq!(out[12..16].to_vec(), vec![0x9b, 0xbc, 0x0f, 0xff]);
+
+    palettes::render_dmg(&shades, &Palette::Pocket, &mut out);
+    assert_eq!(out[..4].to_vec(), vec![0x00, 0x00, 0x00, 0xff]);
+    assert_eq!(out[4..8].to_vec(), vec![0x54, 0x54, 0x54, 0xff]);
+
+    let custom = Palette::parse("e0f8d0,88c070,#346856,081820").unwrap();
+    palettes::render_dmg(&shades, &custom, &mut out);
+    assert_eq!(out[..4].to_vec(), vec![0x08, 0x18, 0x20, 0xff]);
+    assert_eq!(out[12..16].to_vec(), vec![0xe0, 0xf8, 0xd0, 0xff]);
+    assert_eq!(Palette::parse("e0f8d0,88c070,346856"), None);
+    assert_eq!(Palette::parse("pocket"), Some(Palette::Pocket));
+}
+
+#[test]
+fn cgb_colours() {
+    // white, red, green, blue and black in rgb555, red in the low bits
+    let colors: Vec<u16> = [0x7fff, 0x001f, 0x03e0, 0x7c00, 0x0000].iter().cycle().take(WIDTH * HEIGHT).copied().collect();
+    let pixels = |out: &[u8]| out[..20].chunks(4).map(|p| [p[0], p[1], p[2], p[3]]).collect::<Vec<[u8; 4]>>();
+
+    let mut out = frame();
+    palettes::render_cgb(&colors, ColorCorrection::None, &mut out);
+    assert_eq!(pixels(&out), vec![[255, 255, 255, 255], [255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [0, 0, 0, 255]]);
+
+    // darker and mixed, white is capped below full brightness
+    palettes::render_cgb(&colors, ColorCorrection::Cgb, &mut out);
+    assert_eq!(pixels(&out), vec![[240, 240, 240, 255], [201, 0, 46, 255], [31, 186, 31, 255], [15, 62, 170, 255], [0, 0, 0, 255]]);
+}
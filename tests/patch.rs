@@ -0,0 +1,204 @@
+/*
+ips, ups and bps patches built here byte by byte, applied to a small
+rom: round trips for each format, then patches that are cut short, that
+ask for more rom than can exist or that were made for another rom
+*/
+use std::io::ErrorKind;
+
+use gb_emulator::crc32::crc32;
+use gb_emulator::patch;
+
+fn rom() -> Vec<u8> {
+    return (0..64).map(|i| (i * 3) as u8).collect();
+}
+
+// the number encoding of ups and bps
+fn varint(out: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let x = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(0x80 | x);
+            return;
+        }
+        out.push(x);
+        n -= 1;
+    }
+}
+
+// the crc32s of the source, the target and the patch so far
+fn footer(patch: &mut Vec<u8>, source: &[u8], target: &[u8]) {
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let crc = crc32(patch);
+    patch.extend_from_slice(&crc.to_le_bytes());
+}
+
+fn ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"UPS1".to_vec();
+    varint(&mut patch, source.len());
+    varint(&mut patch, target.len());
+    let len = source.len().max(target.len());
+    let xor = |i: usize| source.get(i).copied().unwrap_or(0) ^ target.get(i).copied().unwrap_or(0);
+    let (mut pos, mut i) = (0, 0);
+    while i < len {
+        if xor(i) == 0 {
+            i += 1;
+            continue;
+        }
+        varint(&mut patch, i - pos);
+        while i < len && xor(i) != 0 {
+            patch.push(xor(i));
+            i += 1;
+        }
+        // the 0 ending a hunk takes up a position of its own
+        patch.push(0);
+        i += 1;
+        pos = i;
+    }
+    footer(&mut patch, source, target);
+    return patch;
+}
+
+// a bps patch of `actions` that makes `target` out of `source`
+fn bps(source: &[u8], target: &[u8], target_size: usize, actions: &[u8]) -> Vec<u8> {
+    let mut patch = b"BPS1".to_vec();
+    varint(&mut patch, source.len());
+    varint(&mut patch, target_size);
+    varint(&mut patch, 0);
+    patch.extend_from_slice(actions);
+    footer(&mut patch, source, target);
+    return patch;
+}
+
+fn action(out: &mut Vec<u8>, kind: usize, len: usize) {
+    varint(out, ((len - 1) << 2) | kind);
+}
+
+fn error(result: std::io::Result<Vec<u8>>) -> String {
+    let e = result.expect_err("the patch should be refused");
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    return e.to_string();
+}
+
+#[test]
+fn ips_round_trip() {
+    let mut patch = b"PATCH".to_vec();
+    // three bytes at 0x10
+    patch.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x03, 0xaa, 0xbb, 0xcc]);
+    // 0x55 eight times at 0x40, past the end of the rom
+    patch.extend_from_slice(&[0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x08, 0x55]);
+    patch.extend_from_slice(b"EOF");
+
+    let mut expected = rom();
+    expected[0x10..0x13].copy_from_slice(&[0xaa, 0xbb, 0xcc]);
+    expected.extend_from_slice(&[0x55; 8]);
+    assert_eq!(patch::apply(&rom(), &patch).unwrap(), expected);
+
+    // a size after EOF truncates the rom
+    patch.extend_from_slice(&[0x00, 0x00, 0x20]);
+    assert_eq!(patch::apply(&rom(), &patch).unwrap(), expected[..0x20].to_vec());
+}
+
+#[test]
+fn ups_round_trip() {
+    let source = rom();
+    let mut target = source.clone();
+    target[3] = 0xff;
+    target[4] = 0xfe;
+    target[40] ^= 0x11;
+    target.extend_from_slice(b"more");
+    assert_eq!(patch::apply(&source, &ups(&source, &target)).unwrap(), target);
+
+    let shorter = source[..32].to_vec();
+    assert_eq!(patch::apply(&source, &ups(&source, &shorter)).unwrap(), shorter);
+}
+
+#[test]
+fn bps_round_trip() {
+    let source = rom();
+    let mut target = source[..16].to_vec();
+    target.extend_from_slice(b"HELLO");
+    target.extend_from_slice(&source[40..48]);
+    // a target copy from offset 16 that overlaps what it writes
+    for i in 0..10 {
+        let b = target[16 + i];
+        target.push(b);
+    }
+
+    let mut actions = Vec::new();
+    action(&mut actions, 0, 16);
+    action(&mut actions, 1, 5);
+    actions.extend_from_slice(b"HELLO");
+    action(&mut actions, 2, 8);
+    varint(&mut actions, 40 << 1);
+    action(&mut actions, 3, 10);
+    varint(&mut actions, 16 << 1);
+    assert_eq!(patch::apply(&source, &bps(&source, &target, target.len(), &actions)).unwrap(), target);
+}
+
+#[test]
+fn truncated_patches() {
+    // a record of 16 bytes with only 3 of them there
+    let ips = [b"PATCH".as_ref(), &[0x00, 0x00, 0x10, 0x00, 0x10, 0x01, 0x02, 0x03]].concat();
+    assert!(error(patch::apply(&rom(), &ips)).contains("truncated"));
+    assert!(error(patch::apply(&rom(), b"PATCH")).contains("truncated"));
+    assert!(error(patch::apply(&rom(), b"UPS1")).contains("truncated"));
+
+    // a target read of 8 bytes with 2 left before the footer
+    let mut actions = Vec::new();
+    action(&mut actions, 1, 8);
+    actions.extend_from_slice(&[1, 2]);
+    assert!(error(patch::apply(&rom(), &bps(&rom(), &[], 8, &actions))).contains("truncated"));
+}
+
+#[test]
+fn oversized_patches() {
+    let source = rom();
+
+    // a target read as long as a number can say
+    let mut actions = Vec::new();
+    varint(&mut actions, (usize::MAX >> 2 << 2) | 1);
+    assert!(error(patch::apply(&source, &bps(&source, &[], 16, &actions))).contains("past the end"));
+
+    // more bytes than the target size
+    let mut actions = Vec::new();
+    action(&mut actions, 0, 32);
+    assert!(error(patch::apply(&source, &bps(&source, &[], 16, &actions))).contains("past the end"));
+
+    // a source copy from past the end of the rom
+    let mut actions = Vec::new();
+    action(&mut actions, 2, 8);
+    varint(&mut actions, 60 << 1);
+    assert!(error(patch::apply(&source, &bps(&source, &[], 8, &actions))).contains("outside"));
+
+    // a target far bigger than any rom
+    let mut huge = b"UPS1".to_vec();
+    varint(&mut huge, source.len());
+    varint(&mut huge, usize::MAX / 2);
+    footer(&mut huge, &source, &[]);
+    assert!(error(patch::apply(&source, &huge)).contains("more than"));
+    let huge = bps(&source, &[], usize::MAX / 2, &[]);
+    assert!(error(patch::apply(&source, &huge)).contains("more than"));
+}
+
+#[test]
+fn patches_for_another_rom() {
+    let source = rom();
+    let mut target = source.clone();
+    target[0] = 0x99;
+    let patch = ups(&source, &target);
+
+    let mut other = source.clone();
+    other[1] ^= 1;
+    assert!(error(patch::apply(&other, &patch)).contains("different rom"));
+
+    let mut corrupt = patch.clone();
+    corrupt[6] ^= 1;
+    assert!(error(patch::apply(&source, &corrupt)).contains("corrupt"));
+
+    // the target crc32 is checked too
+    let mut actions = Vec::new();
+    action(&mut actions, 0, 64);
+    assert!(error(patch::apply(&source, &bps(&source, &target, 64, &actions))).contains("does not match"));
+}
@@ -0,0 +1,78 @@
+/*
+profiles a program that calls into two levels of functions, runs an rst
+and takes a vblank interrupt, then checks the call tree through the
+folded stacks and the function table. the program in rom:
+
+    0028  C9        Rst:    RET
+    0040  D9        VBlank: RETI
+    0100  31 FE FF          LD SP,$FFFE
+    0103  3E 01             LD A,$01
+    0105  E0 FF             LDH ($FF),A     ; IE = vblank
+    0107  CD 20 01          CALL Sub
+    010A  EF                RST $28
+    010B  E0 0F             LDH ($0F),A     ; IF = vblank
+    010D  FB                EI
+    010E  00                NOP
+    010F  18 FE             JR $010F
+    0120  CD 30 01  Sub:    CALL Inner
+    0123  C9                RET
+    0130  00        Inner:  NOP
+    0131  C9                RET
+*/
+use gb_emulator::cpu::Z80;
+use gb_emulator::mmu::MMU;
+use gb_emulator::profiler::Profiler;
+use gb_emulator::symbols::Symbols;
+
+const MAIN: [u8; 17] = [0x31, 0xfe, 0xff, 0x3e, 0x01, 0xe0, 0xff, 0xcd, 0x20, 0x01, 0xef, 0xe0, 0x0f, 0xfb, 0x00, 0x18, 0xfe];
+
+fn machine() -> Z80 {
+    let mut rom = vec![0; 0x8000];
+    rom[0x28] = 0xc9;
+    rom[0x40] = 0xd9;
+    rom[0x100..0x100 + MAIN.len()].copy_from_slice(&MAIN);
+    rom[0x120..0x124].copy_from_slice(&[0xcd, 0x30, 0x01, 0xc9]);
+    rom[0x130..0x132].copy_from_slice(&[0x00, 0xc9]);
+    let mut memory_unit = MMU::new();
+    memory_unit.load_rom(&rom);
+    let mut cpu = Z80::new(memory_unit);
+    cpu.reset();
+
+    let mut symbols = Symbols::new();
+    for (name, address) in [("Rst", 0x28), ("VBlank", 0x40), ("Sub", 0x120), ("Inner", 0x130)].iter() {
+        symbols.add(name, 0, *address);
+    }
+    let mut profiler = Profiler::new();
+    profiler.set_symbols(Some(symbols));
+    cpu.set_profiler(Some(profiler));
+    return cpu;
+}
+
+#[test]
+fn calls_rsts_and_interrupts() {
+    let mut cpu = machine();
+    // the 15 steps up to the JR, then the loop 11 times
+    for _ in 0..26 {
+        cpu.run();
+    }
+    let profiler = cpu.profiler().unwrap();
+
+    // the cycles of a CALL, an RST or taking an interrupt are the caller's,
+    // those of the RET or RETI the callee's
+    let root = 12 + 8 + 12 + 20 + 12 + 12 + 4 + 20 + 4 + 12 * 11;
+    assert_eq!(profiler.folded(), format!(
+        "root {}\nroot;Rst 12\nroot;Sub 32\nroot;Sub;Inner 16\nroot;VBlank 12\n", root));
+    assert_eq!(profiler.total(), root + 12 + 32 + 16 + 12);
+    assert_eq!(profiler.pcs()[0], ((0, 0x010f), 12 * 11));
+
+    let functions: Vec<String> = profiler.functions().iter()
+        .map(|f| format!("{} {} {}", f.name, f.own, f.total))
+        .collect();
+    assert_eq!(functions, vec![
+        format!("root {} {}", root, root + 72),
+        String::from("Sub 32 48"),
+        String::from("Inner 16 16"),
+        String::from("Rst 12 12"),
+        String::from("VBlank 12 12")
+    ]);
+}
@@ -0,0 +1,73 @@
+/*
+rewinds a program that keeps changing memory and checks every state
+stepped back to is the one saved at that frame, which takes the xor and
+run length coding of the deltas through a full round trip. the program
+in rom at 0x0100:
+
+    0100  21 00 C0  LD HL,$C000
+    0103  34        INC (HL)
+    0104  2C        INC L
+    0105  18 FC     JR $0103
+*/
+use gb_emulator::cpu::Z80;
+use gb_emulator::mmu::MMU;
+use gb_emulator::rewind::{Rewind, FRAMES_PER_SECOND};
+use gb_emulator::savestate;
+
+const PROGRAM: [u8; 7] = [0x21, 0x00, 0xc0, 0x34, 0x2c, 0x18, 0xfc];
+
+fn machine() -> Z80 {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    let mut memory_unit = MMU::new();
+    memory_unit.load_rom(&rom);
+    let mut cpu = Z80::new(memory_unit);
+    cpu.reset();
+    return cpu;
+}
+
+#[test]
+fn steps_back_through_every_snapshot() {
+    let mut cpu = machine();
+    let mut rewind = Rewind::new(1, 1);
+    let mut states = Vec::new();
+    for _ in 0..20 {
+        cpu.run_frame();
+        rewind.record_frame(&cpu);
+        states.push(savestate::save(&cpu));
+    }
+    assert_eq!(rewind.len(), 20);
+
+    for (n, state) in states.iter().enumerate().rev() {
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert!(savestate::save(&cpu) == *state, "state of frame {} differs", n);
+    }
+    assert!(rewind.is_empty());
+    assert!(!rewind.step_back(&mut cpu).unwrap());
+}
+
+#[test]
+fn drops_the_oldest_snapshots() {
+    let mut cpu = machine();
+    // a second of history with a snapshot every 20 frames
+    let mut rewind = Rewind::new(1, 20);
+    let mut states = Vec::new();
+    for frame in 0..200 {
+        if frame % 20 == 0 { states.push(savestate::save(&cpu)); }
+        rewind.record_frame(&cpu);
+        cpu.run_frame();
+    }
+    assert_eq!(rewind.len(), 1 + FRAMES_PER_SECOND as usize / 20);
+
+    assert_eq!(rewind.rewind(&mut cpu, 1.0).unwrap(), FRAMES_PER_SECOND);
+    assert!(savestate::save(&cpu) == states[states.len() - 3]);
+    assert_eq!(rewind.len(), 1);
+}
+
+#[test]
+fn long_histories_do_not_overflow() {
+    let cpu = machine();
+    let mut rewind = Rewind::new(u32::MAX, 1);
+    rewind.record_frame(&cpu);
+    assert_eq!(rewind.len(), 1);
+}
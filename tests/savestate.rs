@@ -0,0 +1,82 @@
+/*
+save states of an mbc1 cartridge with ram, where every rom bank holds
+its own number: the bank registers, the cartridge ram and whether the
+boot rom is mapped all come back with the state, and states without
+the sections added since the first ones still load
+*/
+use gb_emulator::cpu::Z80;
+use gb_emulator::mbc::ROM_BANK_SIZE;
+use gb_emulator::mmu::MMU;
+use gb_emulator::savestate::{self, JOYPAD_TAG, SERIAL_TAG};
+
+fn machine() -> Z80 {
+    let mut rom: Vec<u8> = (0..8 * ROM_BANK_SIZE).map(|i| (i / ROM_BANK_SIZE) as u8).collect();
+    // mbc1 with 8KB of ram
+    rom[0x147] = 0x03;
+    rom[0x149] = 0x02;
+    let mut memory_unit = MMU::new();
+    memory_unit.load_rom(&rom);
+    let mut cpu = Z80::new(memory_unit);
+    cpu.reset();
+    return cpu;
+}
+
+#[test]
+fn banks_and_cartridge_ram() {
+    let mut cpu = machine();
+    let memory_unit = cpu.memory_mut();
+    memory_unit.set_b(0x0000, 0x0a);
+    memory_unit.set_b(0x2000, 0x05);
+    memory_unit.set_b(0xa010, 0x42);
+    let state = savestate::save(&cpu);
+
+    let memory_unit = cpu.memory_mut();
+    memory_unit.set_b(0x2000, 0x02);
+    memory_unit.set_b(0xa010, 0x00);
+    memory_unit.set_b(0x0000, 0x00);
+    savestate::load(&mut cpu, &state).unwrap();
+    assert_eq!(cpu.memory().peek(0x4000), 5);
+    assert_eq!(cpu.memory().peek(0xa010), 0x42);
+}
+
+#[test]
+fn boot_rom_mapping() {
+    let mut cpu = machine();
+    cpu.memory_mut().set_boot_rom(vec![0x31; 0x100]);
+    let booting = savestate::save(&cpu);
+    cpu.memory_mut().set_b(0xff50, 0x01);
+    let booted = savestate::save(&cpu);
+    assert_eq!(cpu.memory().peek(0x0000), 0x00);
+
+    savestate::load(&mut cpu, &booting).unwrap();
+    assert!(cpu.memory().boot_rom_mapped());
+    assert_eq!(cpu.memory().peek(0x0000), 0x31);
+    savestate::load(&mut cpu, &booted).unwrap();
+    assert!(!cpu.memory().boot_rom_mapped());
+    assert_eq!(cpu.memory().peek(0x0000), 0x00);
+}
+
+// the state without its `tag` section
+fn without(state: &[u8], tag: &[u8; 4]) -> Vec<u8> {
+    let mut out = state[..6].to_vec();
+    let mut pos = 6;
+    while pos < state.len() {
+        let len = u32::from_le_bytes([state[pos + 4], state[pos + 5], state[pos + 6], state[pos + 7]]) as usize;
+        if &state[pos..pos + 4] != tag { out.extend_from_slice(&state[pos..pos + 8 + len]); }
+        pos += 8 + len;
+    }
+    return out;
+}
+
+#[test]
+fn newer_sections_are_optional() {
+    let mut cpu = machine();
+    cpu.memory_mut().set_b(0xff01, 0x42);
+    cpu.memory_mut().set_b(0xff00, 0x10);
+    let state = without(&without(&savestate::save(&cpu), SERIAL_TAG), JOYPAD_TAG);
+    assert!(state.len() < savestate::save(&cpu).len());
+
+    savestate::load(&mut cpu, &state).unwrap();
+    assert_eq!(cpu.memory().peek(0xff01), 0x00);
+    assert_eq!(cpu.memory().peek(0xff00) & 0x30, 0x30);
+}
@@ -0,0 +1,93 @@
+/*
+transfers through the serial port of an MMU: a byte sent over the
+Loopback cable comes back after 8 bits of the internal clock and raises
+the serial interrupt, nothing plugged in shifts in 0xff, the cgb fast
+clock takes 128 cycles, and an externally clocked transfer waits for the
+other end
+*/
+use gb_emulator::mmu::{IF_ADDRESS, MMU, SERIAL_INTERRUPT};
+use gb_emulator::model::Model;
+use gb_emulator::serial::{LinkCable, Loopback};
+
+fn send(memory_unit: &mut MMU, value: u8, sc: u8) {
+    memory_unit.set_b(0xff01, value);
+    memory_unit.set_b(0xff02, sc);
+}
+
+fn interrupted(memory_unit: &MMU) -> bool {
+    return memory_unit.peek(IF_ADDRESS) & SERIAL_INTERRUPT != 0;
+}
+
+#[test]
+fn loopback() {
+    let mut memory_unit = MMU::new();
+    memory_unit.serial.connect(Some(Box::new(Loopback)));
+    send(&mut memory_unit, 0x42, 0x81);
+    assert_eq!(memory_unit.peek(0xff02), 0xff);
+
+    memory_unit.tick(4095);
+    assert!(!interrupted(&memory_unit));
+    memory_unit.tick(1);
+    assert!(interrupted(&memory_unit));
+    assert_eq!(memory_unit.peek(0xff01), 0x42);
+    assert_eq!(memory_unit.peek(0xff02), 0x7f);
+}
+
+#[test]
+fn nothing_plugged_in() {
+    let mut memory_unit = MMU::new();
+    send(&mut memory_unit, 0x42, 0x81);
+    memory_unit.tick(4096);
+    assert!(interrupted(&memory_unit));
+    assert_eq!(memory_unit.peek(0xff01), 0xff);
+}
+
+#[test]
+fn cgb_fast_clock() {
+    let mut memory_unit = MMU::new();
+    memory_unit.set_model(Model::Cgb, true);
+    memory_unit.serial.connect(Some(Box::new(Loopback)));
+    send(&mut memory_unit, 0x42, 0x83);
+    assert_eq!(memory_unit.peek(0xff02), 0xff);
+    memory_unit.tick(128);
+    assert!(interrupted(&memory_unit));
+    assert_eq!(memory_unit.peek(0xff02), 0x7f);
+
+    // on a dmg bit 1 reads as set whatever is written and the clock stays slow
+    let mut memory_unit = MMU::new();
+    send(&mut memory_unit, 0x42, 0x83);
+    assert_eq!(memory_unit.peek(0xff02), 0xff);
+    memory_unit.tick(128);
+    assert!(!interrupted(&memory_unit));
+}
+
+// the other end clocks a byte in on its second poll
+struct Master {
+    polls: u32
+}
+
+impl LinkCable for Master {
+    fn transfer(&mut self, _: u8) -> Option<u8> {
+        return None;
+    }
+
+    fn poll(&mut self, out: u8) -> Option<u8> {
+        self.polls += 1;
+        if self.polls < 2 { return None; }
+        assert_eq!(out, 0x42);
+        return Some(0x99);
+    }
+}
+
+#[test]
+fn external_clock() {
+    let mut memory_unit = MMU::new();
+    memory_unit.serial.connect(Some(Box::new(Master { polls: 0 })));
+    send(&mut memory_unit, 0x42, 0x80);
+    memory_unit.tick(512);
+    assert!(!interrupted(&memory_unit));
+    memory_unit.tick(512);
+    assert!(interrupted(&memory_unit));
+    assert_eq!(memory_unit.peek(0xff01), 0x99);
+    assert_eq!(memory_unit.peek(0xff02), 0x7e);
+}
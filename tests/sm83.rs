@@ -0,0 +1,177 @@
+/*
+per instruction tests driven by the public SM83 single step vectors
+(github.com/SingleStepTests/sm83). put the json files from its v1
+directory in tests/sm83/v1, one file per opcode named "00.json" ...
+"ff.json" and "cb 00.json" ... "cb ff.json". each vector holds:
+
+    {
+        "name": "...",
+        "initial": { "pc", "sp", "a", "b", "c", "d", "e", "f", "h", "l", "ime", "ie", "ram": [[addr, value]...] },
+        "final": { same fields },
+        "cycles": [[addr, value, "r-m"], ...]
+    }
+
+every entry of isa_map and the cb table is run against its file and
+compared on registers, ram, the number of machine cycles taken and the
+reads and writes on the bus, in order (internal cycles, "---", are not
+seen by the MMU hooks and are only counted)
+*/
+use std::cell::RefCell;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde_json::Value;
+
+use gb_emulator::cpu::Z80;
+use gb_emulator::mmu::{Access, MMU};
+
+const REGISTERS: [&str; 10] = ["a", "b", "c", "d", "e", "f", "h", "l", "pc", "sp"];
+
+// the opcodes that do not exist on the sm83 and have no vectors
+const UNUSED: [u8; 12] = [0xcb, 0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd];
+
+fn vector_dir() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("sm83").join("v1");
+}
+
+fn number(state: &Value, field: &str) -> u16 {
+    return state[field].as_u64().unwrap_or(0) as u16;
+}
+
+fn setup(initial: &Value) -> Z80 {
+    let mut memory_unit = MMU::new();
+    if let Some(ram) = initial["ram"].as_array() {
+        for entry in ram {
+            memory_unit.set_b(entry[0].as_u64().unwrap() as u16, entry[1].as_u64().unwrap() as u8);
+        }
+    }
+    memory_unit.set_b(0xffff, number(initial, "ie") as u8);
+
+    let mut cpu = Z80::new(memory_unit);
+    for reg in REGISTERS.iter() {
+        cpu.set_register(reg, number(initial, reg));
+    }
+    cpu.set_register("ime", number(initial, "ime"));
+    return cpu;
+}
+
+// the reads and writes of a vector's cycles as (address, value, 'r' or 'w')
+fn bus_accesses(cycles: &[Value]) -> Vec<(u16, u8, char)> {
+    let mut accesses = Vec::new();
+    for cycle in cycles {
+        let access = match cycle[2].as_str().unwrap_or("---").as_bytes() {
+            [b'r', ..] => 'r',
+            [_, b'w', ..] => 'w',
+            _ => continue
+        };
+        accesses.push((cycle[0].as_u64().unwrap_or(0) as u16, cycle[1].as_u64().unwrap_or(0) as u8, access));
+    }
+    return accesses;
+}
+
+fn describe_bus(accesses: &[(u16, u8, char)]) -> String {
+    let accesses: Vec<String> = accesses.iter().map(|(a, v, k)| format!("{} {:04X}={:02X}", k, a, v)).collect();
+    return accesses.join(" ");
+}
+
+// describes every difference between the cpu and the expected final state
+fn compare(cpu: &Z80, expected: &Value, cycles: usize) -> Vec<String> {
+    let mut errors = Vec::new();
+    for reg in REGISTERS.iter() {
+        let (got, want) = (cpu.register(reg).unwrap(), number(expected, reg));
+        if got != want {
+            errors.push(format!("{}={:X} expected {:X}", reg, got, want));
+        }
+    }
+    if let Some(ram) = expected["ram"].as_array() {
+        for entry in ram {
+            let address = entry[0].as_u64().unwrap() as u16;
+            let (got, want) = (cpu.memory().peek(address), entry[1].as_u64().unwrap() as u8);
+            if got != want {
+                errors.push(format!("[{:04X}]={:02X} expected {:02X}", address, got, want));
+            }
+        }
+    }
+    if cpu.last_cycles() as usize != cycles * 4 {
+        errors.push(format!("took {} t cycles expected {}", cpu.last_cycles(), cycles * 4));
+    }
+    return errors;
+}
+
+fn run_vector(vector: &Value) -> Vec<String> {
+    let cycles = vector["cycles"].as_array().map(|c| c.as_slice()).unwrap_or(&[]);
+    let result = panic::catch_unwind(|| {
+        let mut cpu = setup(&vector["initial"]);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        for access in [Access::Execute, Access::Read, Access::Write].iter() {
+            let seen = seen.clone();
+            let kind = if *access == Access::Write { 'w' } else { 'r' };
+            cpu.memory_mut().add_hook(*access, 0x0000, 0xffff, Box::new(move |event| {
+                seen.borrow_mut().push((event.address, event.value, kind));
+            }));
+        }
+        cpu.run();
+
+        let mut errors = compare(&cpu, &vector["final"], cycles.len());
+        let (seen, wanted) = (seen.borrow(), bus_accesses(cycles));
+        if *seen != wanted {
+            errors.push(format!("bus did {} expected {}", describe_bus(&seen), describe_bus(&wanted)));
+        }
+        return errors;
+    });
+    return match result {
+        Ok(errors) => errors,
+        Err(_) => vec![String::from("panicked")]
+    };
+}
+
+// runs one opcode file, returning (vectors run, first failure)
+fn run_file(path: &Path) -> (usize, Option<String>) {
+    let text = fs::read_to_string(path).expect("could not read test vectors");
+    let vectors: Value = serde_json::from_str(&text).expect("could not parse test vectors");
+    let vectors = vectors.as_array().expect("test vectors should be a list");
+
+    for vector in vectors {
+        let errors = run_vector(vector);
+        if !errors.is_empty() {
+            let name = vector["name"].as_str().unwrap_or("?");
+            return (vectors.len(), Some(format!("{}: {}", name, errors.join(", "))));
+        }
+    }
+    return (vectors.len(), None);
+}
+
+#[test]
+fn sm83_single_step() {
+    let dir = vector_dir();
+    if !dir.is_dir() {
+        println!("no single step vectors in {}, skipping", dir.display());
+        return;
+    }
+
+    let mut names: Vec<String> = (0..=255u8).filter(|op| !UNUSED.contains(op)).map(|op| format!("{:02x}", op)).collect();
+    names.extend((0..=255u8).map(|op| format!("cb {:02x}", op)));
+
+    let mut failed = Vec::new();
+    let mut missing = 0;
+    for name in names.iter() {
+        let path = dir.join(format!("{}.json", name));
+        if !path.is_file() {
+            missing += 1;
+            continue;
+        }
+        let (count, failure) = run_file(&path);
+        match failure {
+            None => println!("PASS {} ({} vectors)", name, count),
+            Some(reason) => {
+                println!("FAIL {} {}", name, reason);
+                failed.push(name.clone());
+            }
+        }
+    }
+
+    println!("{} opcodes failed, {} had no vector file", failed.len(), missing);
+    assert!(failed.is_empty(), "failing opcodes: {}", failed.join(" "));
+}
@@ -0,0 +1,69 @@
+/*
+rgbds style symbol files parsed into labels, then used by the debugger:
+a breakpoint set on a label stops where the label is and is reported by
+its name. the program in rom:
+
+    0100  C3 50 01  JP Main
+    0150  00        Main:      NOP
+    0151  18 FD     Main.loop: JR Main
+*/
+use gb_emulator::cpu::Z80;
+use gb_emulator::debugger::Debugger;
+use gb_emulator::mmu::MMU;
+use gb_emulator::symbols::Symbols;
+
+const SYMBOLS: &str = "; rgblink -n output
+00:0150 Main
+00:0150 Main.start
+
+00:0151 Main.loop   ; the loop
+01:4000 LoadTiles
+00:C000 wBuffer
+";
+
+#[test]
+fn parses_bank_address_label_lines() {
+    let symbols = Symbols::parse(SYMBOLS).unwrap();
+    assert_eq!(symbols.len(), 5);
+    assert_eq!(symbols.lookup("Main.loop"), Some((0, 0x0151)));
+    assert_eq!(symbols.lookup("LoadTiles"), Some((1, 0x4000)));
+    assert_eq!(symbols.lookup("wBuffer"), Some((0, 0xc000)));
+    assert_eq!(symbols.lookup("Missing"), None);
+
+    // a global label names its address over a local one there
+    assert_eq!(symbols.label(0, 0x0150), Some("Main"));
+    assert_eq!(symbols.label(1, 0x4000), Some("LoadTiles"));
+    assert_eq!(symbols.label(2, 0x4000), None);
+}
+
+#[test]
+fn rejects_other_lines() {
+    for (text, line) in [("00:0150 Main\n0150 Other", 2), ("zz:0150 Main", 1), ("00:0150", 1), ("\n\n00:10000 Big", 3)].iter() {
+        let e = Symbols::parse(text).expect_err(text);
+        assert!(e.to_string().contains(&format!("line {} ", line)), "{}: {}", text, e);
+    }
+}
+
+#[test]
+fn label_breakpoints() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+    rom[0x150..0x153].copy_from_slice(&[0x00, 0x18, 0xfd]);
+    let mut memory_unit = MMU::new();
+    memory_unit.load_rom(&rom);
+    let mut cpu = Z80::new(memory_unit);
+    cpu.reset();
+
+    let mut debugger = Debugger::new();
+    debugger.set_symbols(Some(Symbols::parse(SYMBOLS).unwrap()));
+    assert_eq!(debugger.resolve("Main.loop"), Some(0x0151));
+    assert_eq!(debugger.resolve("c000"), Some(0xc000));
+
+    let mut out = Vec::new();
+    debugger.command(&mut cpu, &["b", "Main.loop"], &mut out).unwrap();
+    assert_eq!(debugger.breakpoints(), &[0x0151]);
+    debugger.command(&mut cpu, &["c"], &mut out).unwrap();
+    assert_eq!(cpu.register("pc"), Some(0x0151));
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("breakpoint at 0151 (Main.loop)"), "{}", text);
+}
@@ -0,0 +1,71 @@
+/*
+runs the community test roms kept under tests/roms. the roms are not
+redistributable so they are not checked in, every .gb file found below
+tests/roms is run and the test only fails when one of them does. see
+testrom.rs for how a pass or fail is detected
+
+only blargg's cpu_instrs is expected to pass. the core has no DIV/TIMA
+timer and some instructions take the wrong number of cycles, so
+instr_timing, mem_timing and mooneye's acceptance suite fail or time
+out and are not worth copying in yet
+*/
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+use gb_emulator::testrom::{self, Outcome};
+
+const DEFAULT_TIMEOUT_SECONDS: u64 = 120;
+
+fn find_roms(dir: &Path, found: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_roms(&path, found);
+        } else if path.extension().map(|e| e == "gb" || e == "gbc").unwrap_or(false) {
+            found.push(path);
+        }
+    }
+}
+
+fn timeout_seconds() -> u64 {
+    return env::var("GB_TEST_ROM_TIMEOUT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+}
+
+#[test]
+fn test_roms() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms");
+    let mut roms = Vec::new();
+    find_roms(&root, &mut roms);
+    roms.sort();
+    if roms.is_empty() {
+        println!("no test roms found under {}, skipping", root.display());
+        return;
+    }
+
+    let timeout = timeout_seconds();
+    let mut failures = 0;
+    for path in roms.iter() {
+        let name = path.strip_prefix(&root).unwrap_or(path).display();
+        let rom = fs::read(path).expect("could not read test rom");
+        let outcome = panic::catch_unwind(|| testrom::run(&rom, None, timeout));
+        let (status, detail) = match outcome {
+            Ok(Outcome::Passed(text)) => ("PASS", text),
+            Ok(Outcome::Failed(text)) => ("FAIL", text),
+            Ok(Outcome::TimedOut(text)) => ("TIMEOUT", text),
+            Err(_) => ("PANIC", String::new())
+        };
+        if status != "PASS" { failures += 1; }
+        println!("{:<8} {} {}", status, name, detail.trim().replace('\n', " | "));
+    }
+    println!("{} of {} test roms passed", roms.len() - failures, roms.len());
+    assert_eq!(failures, 0, "{} test roms did not pass", failures);
+}